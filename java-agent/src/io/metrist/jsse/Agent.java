@@ -0,0 +1,42 @@
+package io.metrist.jsse;
+
+import java.lang.instrument.Instrumentation;
+import java.security.Security;
+import javax.net.ssl.SSLContext;
+
+/**
+ * Loaded into running JVMs by the Metrist eBPF agent, which passes the path of a FIFO
+ * to report through as the argument.
+ *
+ * JSSE does TLS in Java, so there is no library call for eBPF to probe. Instead, we put
+ * a security provider in front of the one that does TLS. SSLContexts that get created
+ * from then on hand out sockets and engines that report their plaintext.
+ */
+public final class Agent {
+    private static boolean installed;
+
+    public static void agentmain(String fifo, Instrumentation inst) {
+        install(fifo);
+    }
+
+    public static void premain(String fifo, Instrumentation inst) {
+        install(fifo);
+    }
+
+    private static synchronized void install(String fifo) {
+        // When the eBPF agent restarts, it attaches again with a new FIFO.
+        Reporter.start(fifo);
+        if (installed) {
+            return;
+        }
+        installed = true;
+        try {
+            TracingProvider provider = new TracingProvider();
+            Security.insertProviderAt(provider, 1);
+            // The default context is usually created early on, and cached.
+            SSLContext.setDefault(SSLContext.getInstance("Default", provider));
+        } catch (Exception e) {
+            System.err.println("metrist: cannot install TLS tracing: " + e);
+        }
+    }
+}
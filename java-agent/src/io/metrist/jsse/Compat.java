@@ -0,0 +1,38 @@
+package io.metrist.jsse;
+
+import java.lang.reflect.InvocationTargetException;
+import java.lang.reflect.Method;
+
+/**
+ * We build for Java 8, but sockets and engines got methods for ALPN in Java 9 (and in
+ * later Java 8 updates). Our wrappers declare these too, so they override them where
+ * they exist, and call the wrapped object through reflection.
+ */
+final class Compat {
+    private Compat() {
+    }
+
+    static Object call(Class<?> type, Object target, String name, Class<?>[] parameterTypes, Object... args) {
+        Method method;
+        try {
+            // Through the public type; the implementation classes are not accessible.
+            method = type.getMethod(name, parameterTypes);
+        } catch (NoSuchMethodException e) {
+            throw new UnsupportedOperationException(name);
+        }
+        try {
+            return method.invoke(target, args);
+        } catch (InvocationTargetException e) {
+            Throwable cause = e.getCause();
+            if (cause instanceof RuntimeException) {
+                throw (RuntimeException) cause;
+            }
+            if (cause instanceof Error) {
+                throw (Error) cause;
+            }
+            throw new IllegalStateException(cause);
+        } catch (IllegalAccessException e) {
+            throw new IllegalStateException(e);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+package io.metrist.jsse;
+
+import java.util.function.BooleanSupplier;
+
+/**
+ * What we report for one socket or engine. We only report client connections, and
+ * the eBPF agent only hears about a connection once data goes through it.
+ */
+final class Connection {
+    private final BooleanSupplier isClient;
+    private long id;
+    private boolean closed;
+
+    Connection(BooleanSupplier isClient) {
+        this.isClient = isClient;
+    }
+
+    boolean isReported() {
+        return isClient.getAsBoolean();
+    }
+
+    void report(byte kind, byte[] data, int off, int len) {
+        report(kind, data, off, len, len);
+    }
+
+    // `captured` bytes of `len`.
+    void report(byte kind, byte[] data, int off, int captured, int len) {
+        long id = id();
+        if (id != 0) {
+            Reporter.report(kind, id, data, off, captured, len);
+        }
+    }
+
+    synchronized void close() {
+        if (id != 0 && !closed) {
+            closed = true;
+            Reporter.closed(id);
+        }
+    }
+
+    private synchronized long id() {
+        if (id == 0 && !closed && isReported()) {
+            id = Reporter.newConnection();
+        }
+        return id;
+    }
+}
@@ -0,0 +1,124 @@
+package io.metrist.jsse;
+
+import java.io.IOException;
+import java.io.OutputStream;
+import java.nio.ByteBuffer;
+import java.nio.file.Files;
+import java.nio.file.Paths;
+import java.nio.file.StandardOpenOption;
+import java.util.concurrent.ArrayBlockingQueue;
+import java.util.concurrent.BlockingQueue;
+import java.util.concurrent.atomic.AtomicLong;
+
+/**
+ * Sends events to the eBPF agent. Application threads only put events on a queue, a
+ * thread of our own writes them to the FIFO. If we cannot keep up, events get dropped;
+ * we never hold up the application.
+ *
+ * Every event is a length prefix, followed by kind, connection id, timestamp (the same
+ * clock as the eBPF probes use), full length of the data, and the data itself up to
+ * what the probes capture. Everything is big endian.
+ *
+ * Right after opening the FIFO we send a HELLO, so the eBPF agent knows we are there.
+ */
+final class Reporter {
+    // The event kinds, as numbered by the eBPF agent.
+    static final byte HELLO = 0;
+    static final byte NEW = 1;
+    static final byte WRITE = 2;
+    static final byte FREE = 3;
+    static final byte READ = 4;
+
+    static final int BUFSIZE = 4000;
+
+    private static final int HEADER_LEN = 1 + 8 + 8 + 4;
+    private static final int QUEUE_SIZE = 10000;
+
+    private static final AtomicLong nextId = new AtomicLong(1);
+    private static final BlockingQueue<byte[]> queue = new ArrayBlockingQueue<>(QUEUE_SIZE);
+    private static volatile String fifo;
+    private static Thread sender;
+
+    private Reporter() {
+    }
+
+    static synchronized void start(String path) {
+        fifo = path;
+        if (sender == null) {
+            sender = new Thread(Reporter::run, "metrist-tls-reporter");
+            sender.setDaemon(true);
+            sender.start();
+        }
+        // This also wakes up the sender, to open the new FIFO.
+        report(HELLO, 0, null, 0, 0, 0);
+    }
+
+    static long newConnection() {
+        long id = nextId.getAndIncrement();
+        report(NEW, id, null, 0, 0, 0);
+        return id;
+    }
+
+    static void closed(long id) {
+        report(FREE, id, null, 0, 0, 0);
+    }
+
+    // `captured` bytes of data, out of `len`.
+    static void report(byte kind, long id, byte[] data, int off, int captured, int len) {
+        captured = Math.min(captured, BUFSIZE);
+        ByteBuffer event = ByteBuffer.allocate(4 + HEADER_LEN + captured);
+        event.putInt(HEADER_LEN + captured);
+        event.put(kind).putLong(id).putLong(System.nanoTime()).putInt(len);
+        if (captured > 0) {
+            event.put(data, off, captured);
+        }
+        queue.offer(event.array());
+    }
+
+    private static void run() {
+        String current = null;
+        OutputStream out = null;
+        while (true) {
+            byte[] event;
+            try {
+                event = queue.take();
+            } catch (InterruptedException e) {
+                return;
+            }
+            String path = fifo;
+            if (!path.equals(current)) {
+                close(out);
+                out = null;
+                current = path;
+                // The eBPF agent opens its end before attaching, so this does not block.
+                try {
+                    out = Files.newOutputStream(Paths.get(path), StandardOpenOption.WRITE);
+                } catch (IOException e) {
+                    out = null;
+                }
+            }
+            if (out == null) {
+                // Not attached (anymore); wait for the next attach.
+                continue;
+            }
+            try {
+                out.write(event);
+            } catch (IOException e) {
+                // The eBPF agent went away.
+                close(out);
+                out = null;
+            }
+        }
+    }
+
+    private static void close(OutputStream out) {
+        if (out == null) {
+            return;
+        }
+        try {
+            out.close();
+        } catch (IOException e) {
+            // Nothing to do.
+        }
+    }
+}
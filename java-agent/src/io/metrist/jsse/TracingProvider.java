@@ -0,0 +1,37 @@
+package io.metrist.jsse;
+
+import java.security.NoSuchAlgorithmException;
+import java.security.Provider;
+import java.security.Security;
+import javax.net.ssl.SSLContext;
+
+/**
+ * Provides tracing SSLContexts for the protocols that some other provider implements.
+ * That provider, whichever the application would have gotten without us, keeps doing
+ * the actual work.
+ */
+final class TracingProvider extends Provider {
+    private static final long serialVersionUID = 1L;
+
+    private static final String[] PROTOCOLS = {
+        "Default", "TLS", "TLSv1", "TLSv1.1", "TLSv1.2", "TLSv1.3", "SSL", "SSLv3"
+    };
+
+    @SuppressWarnings("deprecation")
+    TracingProvider() {
+        super("MetristTracing", 1.0, "Reports TLS plaintext to the Metrist eBPF agent");
+        for (String protocol : PROTOCOLS) {
+            Provider[] providers = Security.getProviders("SSLContext." + protocol);
+            if (providers == null) {
+                continue;
+            }
+            final Provider delegate = providers[0];
+            putService(new Service(this, "SSLContext", protocol, TracingSSLContextSpi.class.getName(), null, null) {
+                @Override
+                public Object newInstance(Object param) throws NoSuchAlgorithmException {
+                    return new TracingSSLContextSpi(SSLContext.getInstance(getAlgorithm(), delegate));
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,232 @@
+package io.metrist.jsse;
+
+import java.nio.ByteBuffer;
+import java.util.List;
+import java.util.function.BiFunction;
+import javax.net.ssl.SSLEngine;
+import javax.net.ssl.SSLEngineResult;
+import javax.net.ssl.SSLException;
+import javax.net.ssl.SSLParameters;
+import javax.net.ssl.SSLSession;
+
+/**
+ * An SSLEngine that reports the plaintext that goes into wrap() and comes out of
+ * unwrap(). This is what non-blocking clients use, like the HttpClient in Java 11 and
+ * Netty based ones.
+ */
+final class TracingSSLEngine extends SSLEngine {
+    private final SSLEngine delegate;
+    private final Connection connection;
+
+    TracingSSLEngine(SSLEngine delegate) {
+        super(delegate.getPeerHost(), delegate.getPeerPort());
+        this.delegate = delegate;
+        this.connection = new Connection(delegate::getUseClientMode);
+    }
+
+    @Override
+    public SSLEngineResult wrap(ByteBuffer[] srcs, int offset, int length, ByteBuffer dst) throws SSLException {
+        if (!connection.isReported()) {
+            return delegate.wrap(srcs, offset, length, dst);
+        }
+        ByteBuffer[] before = duplicate(srcs, offset, length);
+        SSLEngineResult result = delegate.wrap(srcs, offset, length, dst);
+        report(Reporter.WRITE, before, result.bytesConsumed());
+        return result;
+    }
+
+    @Override
+    public SSLEngineResult unwrap(ByteBuffer src, ByteBuffer[] dsts, int offset, int length) throws SSLException {
+        if (!connection.isReported()) {
+            return delegate.unwrap(src, dsts, offset, length);
+        }
+        ByteBuffer[] before = duplicate(dsts, offset, length);
+        SSLEngineResult result = delegate.unwrap(src, dsts, offset, length);
+        report(Reporter.READ, before, result.bytesProduced());
+        return result;
+    }
+
+    @Override
+    public void closeInbound() throws SSLException {
+        delegate.closeInbound();
+        connection.close();
+    }
+
+    @Override
+    public void closeOutbound() {
+        delegate.closeOutbound();
+        connection.close();
+    }
+
+    // The buffers as they were before the call, so we can read what went into or came
+    // out of them.
+    private static ByteBuffer[] duplicate(ByteBuffer[] buffers, int offset, int length) {
+        ByteBuffer[] copies = new ByteBuffer[length];
+        for (int i = 0; i < length; i++) {
+            copies[i] = buffers[offset + i].duplicate();
+        }
+        return copies;
+    }
+
+    private void report(byte kind, ByteBuffer[] buffers, int len) {
+        if (len <= 0) {
+            return;
+        }
+        byte[] data = new byte[Math.min(len, Reporter.BUFSIZE)];
+        int captured = 0;
+        for (ByteBuffer buffer : buffers) {
+            int n = Math.min(buffer.remaining(), data.length - captured);
+            buffer.get(data, captured, n);
+            captured += n;
+            if (captured == data.length) {
+                break;
+            }
+        }
+        connection.report(kind, data, 0, captured, len);
+    }
+
+    // Everything else is done by the engine we wrap.
+
+    @Override
+    public Runnable getDelegatedTask() {
+        return delegate.getDelegatedTask();
+    }
+
+    @Override
+    public boolean isInboundDone() {
+        return delegate.isInboundDone();
+    }
+
+    @Override
+    public boolean isOutboundDone() {
+        return delegate.isOutboundDone();
+    }
+
+    @Override
+    public String[] getSupportedCipherSuites() {
+        return delegate.getSupportedCipherSuites();
+    }
+
+    @Override
+    public String[] getEnabledCipherSuites() {
+        return delegate.getEnabledCipherSuites();
+    }
+
+    @Override
+    public void setEnabledCipherSuites(String[] suites) {
+        delegate.setEnabledCipherSuites(suites);
+    }
+
+    @Override
+    public String[] getSupportedProtocols() {
+        return delegate.getSupportedProtocols();
+    }
+
+    @Override
+    public String[] getEnabledProtocols() {
+        return delegate.getEnabledProtocols();
+    }
+
+    @Override
+    public void setEnabledProtocols(String[] protocols) {
+        delegate.setEnabledProtocols(protocols);
+    }
+
+    @Override
+    public SSLSession getSession() {
+        return delegate.getSession();
+    }
+
+    @Override
+    public SSLSession getHandshakeSession() {
+        return delegate.getHandshakeSession();
+    }
+
+    @Override
+    public void beginHandshake() throws SSLException {
+        delegate.beginHandshake();
+    }
+
+    @Override
+    public SSLEngineResult.HandshakeStatus getHandshakeStatus() {
+        return delegate.getHandshakeStatus();
+    }
+
+    @Override
+    public void setUseClientMode(boolean mode) {
+        delegate.setUseClientMode(mode);
+    }
+
+    @Override
+    public boolean getUseClientMode() {
+        return delegate.getUseClientMode();
+    }
+
+    @Override
+    public void setNeedClientAuth(boolean need) {
+        delegate.setNeedClientAuth(need);
+    }
+
+    @Override
+    public boolean getNeedClientAuth() {
+        return delegate.getNeedClientAuth();
+    }
+
+    @Override
+    public void setWantClientAuth(boolean want) {
+        delegate.setWantClientAuth(want);
+    }
+
+    @Override
+    public boolean getWantClientAuth() {
+        return delegate.getWantClientAuth();
+    }
+
+    @Override
+    public void setEnableSessionCreation(boolean flag) {
+        delegate.setEnableSessionCreation(flag);
+    }
+
+    @Override
+    public boolean getEnableSessionCreation() {
+        return delegate.getEnableSessionCreation();
+    }
+
+    @Override
+    public SSLParameters getSSLParameters() {
+        return delegate.getSSLParameters();
+    }
+
+    @Override
+    public void setSSLParameters(SSLParameters params) {
+        delegate.setSSLParameters(params);
+    }
+
+    public String getApplicationProtocol() {
+        return (String) Compat.call(SSLEngine.class, delegate, "getApplicationProtocol", new Class<?>[0]);
+    }
+
+    public String getHandshakeApplicationProtocol() {
+        return (String) Compat.call(SSLEngine.class, delegate, "getHandshakeApplicationProtocol", new Class<?>[0]);
+    }
+
+    // The selector gets the engine it is called for, which should be us.
+    public void setHandshakeApplicationProtocolSelector(final BiFunction<SSLEngine, List<String>, String> selector) {
+        BiFunction<SSLEngine, List<String>, String> wrapped = selector == null
+                ? null
+                : (engine, protocols) -> selector.apply(this, protocols);
+        Compat.call(SSLEngine.class, delegate, "setHandshakeApplicationProtocolSelector",
+                new Class<?>[] {BiFunction.class}, wrapped);
+    }
+
+    @SuppressWarnings("unchecked")
+    public BiFunction<SSLEngine, List<String>, String> getHandshakeApplicationProtocolSelector() {
+        return (BiFunction<SSLEngine, List<String>, String>) Compat.call(SSLEngine.class, delegate,
+                "getHandshakeApplicationProtocolSelector", new Class<?>[0]);
+    }
+
+    @Override
+    public String toString() {
+        return delegate.toString();
+    }
+}
@@ -0,0 +1,170 @@
+use redbpf_probes::uprobe::prelude::*;
+use probes::tls_mon::*;
+
+// DNS lookups through the C library. We time them and report what they found, so user
+// mode can tell which name a process had in mind when it connected to an address.
+//
+// Probes on the start of a function are named after it with "_enter" added. All other
+// functions in here _MUST_ be the same as the library function names they probe!
+
+// int getaddrinfo(const char *node, const char *service,
+//                 const struct addrinfo *hints, struct addrinfo **res);
+#[allow(unused_must_use)]
+#[uprobe]
+fn getaddrinfo_enter(regs: Registers) {
+    unsafe {
+        DNS_CALLS.set(&bpf_get_current_pid_tgid(), &[bpf_ktime_get_ns(), regs.parm1(), regs.parm4()]);
+    }
+}
+
+// struct addrinfo is ai_flags, ai_family, ai_socktype, ai_protocol (ints), ai_addrlen
+// (with padding), then pointers to ai_addr, ai_canonname and ai_next.
+const ADDRINFO_ADDRLEN: usize = 2;
+const ADDRINFO_ADDR: usize = 3;
+const ADDRINFO_NEXT: usize = 5;
+
+#[allow(unused_must_use)]
+#[uretprobe]
+fn getaddrinfo(regs: Registers) {
+    unsafe {
+        let pid_tgid = bpf_get_current_pid_tgid();
+        let call = match DNS_CALLS.get(&pid_tgid) {
+            Some(call) => *call,
+            None => return,
+        };
+        DNS_CALLS.delete(&pid_tgid);
+        if call[1] == 0 {
+            // Only a service to look up.
+            return;
+        }
+
+        let rc = regs.rc() as i32;
+        let event = TMP_EVENT.get_mut(0).unwrap();
+        start_event(event, pid_tgid, &call, rc as i64 as u64);
+
+        let mut count = 0;
+        if rc == 0 {
+            let mut info: u64 = 0;
+            bpf_probe_read_user(&mut info as *mut _ as *mut c_void, 8, call[2] as *const _);
+            // There is an entry per address and socket type, so the same address
+            // can come up a few times.
+            for idx in 0..MAX_DNS_ADDRS {
+                if info == 0 {
+                    break;
+                }
+                let mut entry = [0u64; 6];
+                if bpf_probe_read_user(entry.as_mut_ptr() as *mut _, 48, info as *const _) < 0 {
+                    break;
+                }
+                let addr_len = entry[ADDRINFO_ADDRLEN] as u32;
+                let slot = event.data.as_mut_ptr().add(DNS_ADDRS_OFFSET + idx * DNS_ADDR_LEN);
+                let err =
+                    bpf_probe_read_user(
+                        slot as *mut _,
+                        if addr_len > DNS_ADDR_LEN as u32 { DNS_ADDR_LEN as u32 } else { addr_len },
+                        entry[ADDRINFO_ADDR] as *const _);
+                if err < 0 {
+                    break;
+                }
+                count = idx + 1;
+                info = entry[ADDRINFO_NEXT];
+            }
+        }
+        event.len = DNS_ADDRS_OFFSET + count * DNS_ADDR_LEN;
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
+
+// struct hostent *gethostbyname(const char *name);
+#[allow(unused_must_use)]
+#[uprobe]
+fn gethostbyname_enter(regs: Registers) {
+    unsafe {
+        DNS_CALLS.set(&bpf_get_current_pid_tgid(), &[bpf_ktime_get_ns(), regs.parm1(), 0]);
+    }
+}
+
+// struct hostent is h_name and h_aliases (pointers), h_addrtype and h_length (ints),
+// then h_addr_list, a list of pointers to addresses that ends with a null pointer.
+const HOSTENT_ADDRTYPE: usize = 2;
+const HOSTENT_ADDR_LIST: usize = 3;
+
+// gethostbyname() returns NULL and sets h_errno, which we cannot get at. We report
+// that as EAI_NONAME.
+const EAI_NONAME: i64 = -2;
+
+#[allow(unused_must_use)]
+#[uretprobe]
+fn gethostbyname(regs: Registers) {
+    unsafe {
+        let pid_tgid = bpf_get_current_pid_tgid();
+        let call = match DNS_CALLS.get(&pid_tgid) {
+            Some(call) => *call,
+            None => return,
+        };
+        DNS_CALLS.delete(&pid_tgid);
+
+        let hostent = regs.rc();
+        let event = TMP_EVENT.get_mut(0).unwrap();
+        start_event(event, pid_tgid, &call, if hostent == 0 { EAI_NONAME as u64 } else { 0 });
+
+        let mut count = 0;
+        let mut header = [0u64; 4];
+        if hostent != 0 &&
+            bpf_probe_read_user(header.as_mut_ptr() as *mut _, 32, hostent as *const _) >= 0 {
+            // h_addrtype and h_length share a word.
+            let family = header[HOSTENT_ADDRTYPE] as u32;
+            let (addr_offset, addr_len) = match family {
+                2 => (4, 4),
+                10 => (8, 16),
+                _ => (0, 0),
+            };
+            for idx in 0..MAX_DNS_ADDRS {
+                if addr_len == 0 {
+                    break;
+                }
+                let mut addr: u64 = 0;
+                bpf_probe_read_user(
+                    &mut addr as *mut _ as *mut c_void,
+                    8,
+                    (header[HOSTENT_ADDR_LIST] + idx as u64 * 8) as *const _);
+                if addr == 0 {
+                    break;
+                }
+                // Make it look like the struct sockaddr that getaddrinfo() returns.
+                let slot = event.data.as_mut_ptr().add(DNS_ADDRS_OFFSET + idx * DNS_ADDR_LEN);
+                *(slot as *mut u16) = family as u16;
+                *(slot.add(2) as *mut u16) = 0;
+                let err =
+                    bpf_probe_read_user(
+                        slot.add(addr_offset) as *mut _,
+                        addr_len,
+                        addr as *const _);
+                if err < 0 {
+                    break;
+                }
+                count = idx + 1;
+            }
+        }
+        event.len = DNS_ADDRS_OFFSET + count * DNS_ADDR_LEN;
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
+
+#[inline(always)]
+unsafe fn start_event(event: &mut TlsEvent, pid_tgid: u64, call: &[u64; 3], rc: u64) {
+    event.kind = Kind::Dns;
+    event.handle = rc;
+    event.ts = bpf_ktime_get_ns();
+    event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+    event.tgid = (pid_tgid >> 32) as u32;
+    event.data[0..8].copy_from_slice(&call[0].to_ne_bytes());
+    let err =
+        bpf_probe_read_user_str(
+            event.data.as_mut_ptr().add(DNS_NAME_OFFSET) as *mut _,
+            DNS_NAME_LEN as u32,
+            call[1] as *const _);
+    if err < 0 {
+        event.data[DNS_NAME_OFFSET] = 0;
+    }
+}
@@ -0,0 +1,134 @@
+use redbpf_probes::uprobe::prelude::*;
+use probes::tls_mon::*;
+
+// GnuTLS, as used by curl, wget, git etc. on Debian based systems. A gnutls_session_t
+// is a pointer, so it works as a handle just like an SSL* does.
+
+// All functions in here _MUST_ be the same as the library function names they probe!
+
+// ssize_t gnutls_record_send(gnutls_session_t session, const void *data, size_t data_size);
+//
+// Unlike SSL_write, we look at this on return. With non-blocking sockets, GnuTLS
+// returns GNUTLS_E_AGAIN and the caller has to repeat the call with the same data,
+// so only a positive return value means that something got written.
+#[allow(unused_must_use)]
+#[uretprobe]
+fn gnutls_record_send(regs: Registers, parms: [u64; 5]) {
+    unsafe {
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+        event.kind = Kind::Write;
+        event.library = Library::GnuTls;
+        event.handle = parms[0];
+        event.ts = bpf_ktime_get_ns();
+
+        let pid_tgid = bpf_get_current_pid_tgid();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+
+        let data = parms[1] as *const u8;
+        let len = regs.rc() as i64;
+        if len > 0 {
+            let err =
+                bpf_probe_read_user(
+                    event.data.as_mut_ptr() as *mut _,
+                    if len > (BUFSIZE as i64) { BUFSIZE as u32 } else { len as u32 },
+                    data as *const _);
+            if err < 0 {
+                printk!("error %lld on bpf_probe_read_user", err);
+            } else {
+                event.len = len as usize;
+                TLS_BUF.insert(regs.ctx, &event);
+            }
+        }
+    }
+}
+
+// ssize_t gnutls_record_recv(gnutls_session_t session, void *data, size_t data_size);
+#[allow(unused_must_use)]
+#[uretprobe]
+fn gnutls_record_recv(regs: Registers, parms: [u64; 5]) {
+    unsafe {
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+        event.kind = Kind::Read;
+        event.library = Library::GnuTls;
+        event.handle = parms[0];
+        event.ts = bpf_ktime_get_ns();
+
+        let pid_tgid = bpf_get_current_pid_tgid();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+
+        let data = parms[1] as *const u8;
+        let len = regs.rc() as i64;
+        if len > 0 {
+            let err =
+                bpf_probe_read_user(
+                    event.data.as_mut_ptr() as *mut _,
+                    if len > (BUFSIZE as i64) { BUFSIZE as u32 } else { len as u32 },
+                    data as *const _);
+            if err < 0 {
+                printk!("error %lld on bpf_probe_read_user", err);
+            } else {
+                event.len = len as usize;
+                TLS_BUF.insert(regs.ctx, &event);
+            }
+        }
+    }
+}
+
+// int gnutls_init(gnutls_session_t *session, unsigned int flags);
+//
+// The session is handed back through the first argument, so we pick it up from
+// there once the call succeeded.
+#[allow(unused_must_use)]
+#[uretprobe]
+fn gnutls_init(regs: Registers, parms: [u64; 5]) {
+    unsafe {
+        if regs.rc() as i32 != 0 {
+            return;
+        }
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+
+        event.kind = Kind::New;
+        event.library = Library::GnuTls;
+        event.ts = bpf_ktime_get_ns();
+
+        let mut session: u64 = 0;
+        let err =
+            bpf_probe_read_user(
+                &mut session as *mut _ as *mut c_void,
+                8,
+                parms[0] as *const _);
+        if err < 0 {
+            printk!("error %lld reading session", err);
+            return;
+        }
+        event.handle = session;
+
+        let pid_tgid = bpf_get_current_pid_tgid();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
+
+// void gnutls_deinit(gnutls_session_t session);
+#[allow(unused_must_use)]
+#[uprobe]
+fn gnutls_deinit(regs: Registers) {
+    unsafe {
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+
+        event.kind = Kind::Free;
+        event.library = Library::GnuTls;
+        event.handle = regs.parm1();
+        event.ts = bpf_ktime_get_ns();
+
+        let pid_tgid = bpf_get_current_pid_tgid();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
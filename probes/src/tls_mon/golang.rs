@@ -0,0 +1,124 @@
+use redbpf_probes::uprobe::prelude::*;
+use probes::tls_mon::*;
+
+// Go's crypto/tls, linked into every Go binary that does TLS. These are not attached
+// by name; user mode finds the functions in the binary and attaches at their file
+// offsets (see src/golang.rs in the agent).
+//
+// Go (1.17 and later) passes arguments and results in registers, in the order RAX,
+// RBX, RCX, RDI, ... A slice takes three: pointer, length, capacity. R14 always holds
+// the current goroutine. For
+//
+//   func (c *Conn) Write(b []byte) (int, error)
+//   func (c *Conn) Read(b []byte) (int, error)
+//
+// this means that `c` is in RAX and `b` in RBX and RCX, and that on return the
+// number of bytes is in RAX.
+
+// Write, on entry. Go's Write does not return until everything is written or
+// the connection is broken.
+#[allow(unused_must_use)]
+#[uprobe]
+fn go_tls_write(regs: Registers) {
+    unsafe {
+        let ctx = &*regs.ctx;
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+        event.kind = Kind::Write;
+        event.library = Library::Go;
+        event.handle = ctx.rax;
+        event.ts = bpf_ktime_get_ns();
+
+        let pid_tgid = bpf_get_current_pid_tgid();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+
+        let data = ctx.rbx as *const u8;
+        let len = ctx.rcx as i64;
+        if len > 0 {
+            let err =
+                bpf_probe_read_user(
+                    event.data.as_mut_ptr() as *mut _,
+                    if len > (BUFSIZE as i64) { BUFSIZE as u32 } else { len as u32 },
+                    data as *const _);
+            if err < 0 {
+                printk!("error %lld on bpf_probe_read_user", err);
+            } else {
+                event.len = len as usize;
+                TLS_BUF.insert(regs.ctx, &event);
+            }
+        }
+    }
+}
+
+// Read, on entry. We keep the arguments around until the goroutine returns from
+// the call, which may well be on another thread.
+#[allow(unused_must_use)]
+#[uprobe]
+fn go_tls_read_enter(regs: Registers) {
+    unsafe {
+        let ctx = &*regs.ctx;
+        let key = [bpf_get_current_pid_tgid() >> 32, ctx.r14];
+        GO_TLS_READS.set(&key, &[ctx.rax, ctx.rbx]);
+    }
+}
+
+// Read, at each of its RET instructions.
+#[allow(unused_must_use)]
+#[uprobe]
+fn go_tls_read_exit(regs: Registers) {
+    unsafe {
+        let ctx = &*regs.ctx;
+        let pid_tgid = bpf_get_current_pid_tgid();
+        let key = [pid_tgid >> 32, ctx.r14];
+        let args = match GO_TLS_READS.get(&key) {
+            Some(args) => *args,
+            None => return,
+        };
+        GO_TLS_READS.delete(&key);
+
+        let len = ctx.rax as i64;
+        if len <= 0 {
+            return;
+        }
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+        event.kind = Kind::Read;
+        event.library = Library::Go;
+        event.handle = args[0];
+        event.ts = bpf_ktime_get_ns();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+
+        let err =
+            bpf_probe_read_user(
+                event.data.as_mut_ptr() as *mut _,
+                if len > (BUFSIZE as i64) { BUFSIZE as u32 } else { len as u32 },
+                args[1] as *const _);
+        if err < 0 {
+            printk!("error %lld on bpf_probe_read_user", err);
+        } else {
+            event.len = len as usize;
+            TLS_BUF.insert(regs.ctx, &event);
+        }
+    }
+}
+
+// func (c *Conn) Close() error
+#[allow(unused_must_use)]
+#[uprobe]
+fn go_tls_close(regs: Registers) {
+    unsafe {
+        let ctx = &*regs.ctx;
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+
+        event.kind = Kind::Free;
+        event.library = Library::Go;
+        event.handle = ctx.rax;
+        event.ts = bpf_ktime_get_ns();
+
+        let pid_tgid = bpf_get_current_pid_tgid();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
@@ -38,6 +38,9 @@ pub fn do_sys_openat2(regs: Registers, parms: [u64; 5]) {
 // Most executable emit a ton of open calls when starting up. Make sure we
 // only pick out the ones for libraries we actually want to trace.
 //
+// We also probe the C library for DNS lookups, but nearly every process opens that,
+// so user mode looks for it on exec instead.
+//
 // Note that `len` here includes the null terminator.
 fn ignore(data: &[u8; BUFSIZE], len: usize) -> bool {
     if len > 0 && data[0] != b'/' {
@@ -87,7 +90,193 @@ fn ignore(data: &[u8; BUFSIZE], len: usize) -> bool {
         data[len- 4] == b'.' {
          false
     }
+    // GnuTLS, "libgnutls.so.[0-9][0-9]\0"
+    else if len > 16 &&
+        data[len-16] == b'l' &&
+        data[len-15] == b'i' &&
+        data[len-14] == b'b' &&
+        data[len-13] == b'g' &&
+        data[len-12] == b'n' &&
+        data[len-11] == b'u' &&
+        data[len-10] == b't' &&
+        data[len- 9] == b'l' &&
+        data[len- 8] == b's' &&
+        data[len- 7] == b'.' &&
+        data[len- 6] == b's' &&
+        data[len- 5] == b'o' &&
+        data[len- 4] == b'.' {
+         false
+    }
+    // NSS does its I/O through NSPR, "libnspr4.so\0"...
+    else if len > 12 &&
+        data[len-12] == b'l' &&
+        data[len-11] == b'i' &&
+        data[len-10] == b'b' &&
+        data[len- 9] == b'n' &&
+        data[len- 8] == b's' &&
+        data[len- 7] == b'p' &&
+        data[len- 6] == b'r' &&
+        data[len- 5] == b'4' &&
+        data[len- 4] == b'.' &&
+        data[len- 3] == b's' &&
+        data[len- 2] == b'o' {
+         false
+    }
+    // ...and sets up SSL file descriptors in "libssl3.so\0".
+    else if len > 11 &&
+        data[len-11] == b'l' &&
+        data[len-10] == b'i' &&
+        data[len- 9] == b'b' &&
+        data[len- 8] == b's' &&
+        data[len- 7] == b's' &&
+        data[len- 6] == b'l' &&
+        data[len- 5] == b'3' &&
+        data[len- 4] == b'.' &&
+        data[len- 3] == b's' &&
+        data[len- 2] == b'o' {
+         false
+    }
+    // Java does TLS in Java, but we want to know about JVMs starting, "libjvm.so\0".
+    else if len > 10 &&
+        data[len-10] == b'l' &&
+        data[len- 9] == b'i' &&
+        data[len- 8] == b'b' &&
+        data[len- 7] == b'j' &&
+        data[len- 6] == b'v' &&
+        data[len- 5] == b'm' &&
+        data[len- 4] == b'.' &&
+        data[len- 3] == b's' &&
+        data[len- 2] == b'o' {
+         false
+    }
     else {
         true
     }
 }
+
+// void setup_new_exec(struct linux_binprm * bprm)
+// Called for every exec, in the context of the process that is now running the new
+// executable. User mode looks at /proc/<pid>/exe to see whether it is something
+// we want to probe, like a Go binary.
+#[allow(unused_must_use)]
+#[kprobe]
+pub fn setup_new_exec(regs: Registers) {
+    unsafe {
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+        event.kind = Kind::Exec;
+        event.ts = bpf_ktime_get_ns();
+
+        let pid_tgid = bpf_get_current_pid_tgid();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+        event.len = 0;
+
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
+
+// int __sys_connect(int fd, struct sockaddr __user *uservaddr, int addrlen)
+// Where sockets go. The TLS probes tell us which socket a connection runs over, and
+// user mode puts the two together. Non-blocking sockets are still connecting when
+// this returns.
+#[allow(unused_must_use)]
+#[kretprobe]
+pub fn __sys_connect(regs: Registers, parms: [u64; 5]) {
+    unsafe {
+        let pid_tgid = bpf_get_current_pid_tgid();
+        let call = match CONNECT_CALLS.get(&pid_tgid) {
+            Some(call) => *call,
+            None => [0, 0],
+        };
+        CONNECT_CALLS.delete(&pid_tgid);
+
+        let rc = regs.rc() as i64;
+        if rc != 0 && rc != -115 {
+            return;
+        }
+        let addr = parms[1] as *const u8;
+        let mut family: u16 = 0;
+        if bpf_probe_read_user(&mut family as *mut _ as *mut c_void, 2, addr as *const _) < 0 {
+            return;
+        }
+        // AF_INET and AF_INET6, which take 16 and 28 bytes.
+        let len = match family {
+            2 => 16,
+            10 => 28,
+            _ => return,
+        };
+
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+        event.kind = Kind::Connect;
+        event.handle = parms[0];
+        event.ts = bpf_ktime_get_ns();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+
+        let err =
+            bpf_probe_read_user(
+                event.data.as_mut_ptr() as *mut _,
+                len as u32,
+                addr as *const _);
+        if err < 0 {
+            printk!("error %lld on bpf_probe_read_user", err);
+        } else {
+            event.data[CONNECT_SOCK_OFFSET..CONNECT_SOCK_OFFSET + 8]
+                .copy_from_slice(&call[0].to_ne_bytes());
+            event.data[CONNECT_SOCK_OFFSET + 8..CONNECT_LEN]
+                .copy_from_slice(&call[1].to_ne_bytes());
+            event.len = CONNECT_LEN;
+            TLS_BUF.insert(regs.ctx, &event);
+            if call[0] != 0 {
+                CONNECTED_SOCKS.set(&call[0], &1);
+            }
+        }
+    }
+}
+
+// int tcp_v4_connect(struct sock *sk, struct sockaddr *uaddr, int addr_len)
+// Called from connect() once the address is known, so anything that went into finding
+// it (like DNS) is not part of the connect time.
+#[kprobe]
+pub fn tcp_v4_connect(regs: Registers) {
+    unsafe { start_connect(regs.parm1()); }
+}
+
+// static int tcp_v6_connect(struct sock *sk, struct sockaddr *uaddr, int addr_len)
+#[kprobe]
+pub fn tcp_v6_connect(regs: Registers) {
+    unsafe { start_connect(regs.parm1()); }
+}
+
+#[allow(unused_must_use)]
+#[inline(always)]
+unsafe fn start_connect(sk: u64) {
+    let ts = bpf_ktime_get_ns();
+    CONNECT_CALLS.set(&bpf_get_current_pid_tgid(), &[sk, ts]);
+    CONNECTING.set(&sk, &ts);
+}
+
+// void tcp_finish_connect(struct sock *sk, struct sk_buff *skb)
+// The SYN-ACK is in. With non-blocking sockets, connect() has long returned by then.
+// This runs when the packet comes in, so not in the context of the process.
+#[allow(unused_must_use)]
+#[kprobe]
+pub fn tcp_finish_connect(regs: Registers) {
+    unsafe {
+        let sk = regs.parm1();
+        if CONNECTING.get(&sk).is_none() {
+            return;
+        }
+        CONNECTING.delete(&sk);
+
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+        event.kind = Kind::Connected;
+        event.handle = sk;
+        event.ts = bpf_ktime_get_ns();
+        event.pid = 0;
+        event.tgid = 0;
+        event.len = 0;
+
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
@@ -3,7 +3,13 @@
 
 use redbpf_macros::program;
 
+pub mod gnutls;
+pub mod golang;
+pub mod dns;
 pub mod kernel;
+pub mod nss;
+pub mod rustls;
+pub mod socket;
 pub mod user;
 
 program!(0xFFFFFFFE, "GPL");
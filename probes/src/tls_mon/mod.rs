@@ -15,6 +15,69 @@ pub static mut TMP_EVENT: PerCpuArray<TlsEvent> = PerCpuArray::with_max_entries(
 #[map]
 pub static mut TLS_BUF: PerfMap<TlsEvent> = PerfMap::with_max_entries(1000000);
 
+// NSPR file descriptors that have the NSS SSL layer on top, keyed by tgid and
+// PRFileDesc pointer. We only want to see reads and writes on these. Processes that
+// exit without closing them leave them behind, so these forget the oldest entries.
+#[map]
+pub static mut NSS_SSL_FDS: LruHashMap<[u64; 2], u8> = LruHashMap::with_max_entries(10240);
+
+// Arguments of Go crypto/tls reads (connection and buffer) that are in progress,
+// keyed by tgid and goroutine. Idle keep-alive connections have a goroutine waiting
+// in Read until their process exits, so this forgets the oldest entries.
+#[map]
+pub static mut GO_TLS_READS: LruHashMap<[u64; 2], [u64; 2]> = LruHashMap::with_max_entries(10240);
+
+// Plaintext HTTP: the remote ports and the process names (comm) whose TCP sockets we
+// look at. Filled by user mode.
+#[map]
+pub static mut PLAIN_PORTS: HashMap<u16, u8> = HashMap::with_max_entries(64);
+
+#[map]
+pub static mut PLAIN_PROCESSES: HashMap<[u8; 16], u8> = HashMap::with_max_entries(64);
+
+// The TCP socket (struct sock pointer) that a thread is reading from or writing to,
+// with the size of the call and whether it is a kTLS socket; keyed by pid/tgid. System
+// calls that we don't have a probe for leave entries behind, so this forgets the oldest.
+#[map]
+pub static mut SOCK_CALLS: LruHashMap<u64, [u64; 3]> = LruHashMap::with_max_entries(10240);
+
+// TCP sockets that we looked at: 1 if they carry HTTP, 0 if not.
+#[map]
+pub static mut PLAIN_SOCKS: HashMap<u64, u8> = HashMap::with_max_entries(10240);
+
+// Kernel TLS sockets, with the SSL* of the OpenSSL connection that runs them, or 0
+// if the application does kTLS by itself.
+#[map]
+pub static mut KTLS_SOCKS: HashMap<u64, u64> = HashMap::with_max_entries(10240);
+
+// The SSL* that a thread is calling SSL_write on; keyed by pid/tgid. Threads that
+// exit in the middle of a call leave entries behind, so this forgets the oldest.
+#[map]
+pub static mut SSL_CALLS: LruHashMap<u64, u64> = LruHashMap::with_max_entries(10240);
+
+// TCP connects that are in progress: the socket and when it started, keyed by pid/tgid
+// until connect() returns, and the start keyed by socket until the connection is up.
+// Connects that fail never finish, so these forget the oldest entries.
+#[map]
+pub static mut CONNECT_CALLS: LruHashMap<u64, [u64; 2]> = LruHashMap::with_max_entries(10240);
+
+#[map]
+pub static mut CONNECTING: LruHashMap<u64, u64> = LruHashMap::with_max_entries(10240);
+
+// Sockets that we reported a connect() for, so we can tell user mode when they close.
+#[map]
+pub static mut CONNECTED_SOCKS: LruHashMap<u64, u8> = LruHashMap::with_max_entries(10240);
+
+// TLS handshakes that are in progress, by SSL*, with when they started.
+#[map]
+pub static mut HANDSHAKES: LruHashMap<u64, u64> = LruHashMap::with_max_entries(10240);
+
+// DNS lookups that are in progress: when they started, the name and where the result
+// goes; keyed by pid/tgid. Threads that get killed or cancelled in the middle of a
+// lookup leave entries behind, so this forgets the oldest.
+#[map]
+pub static mut DNS_CALLS: LruHashMap<u64, [u64; 3]> = LruHashMap::with_max_entries(10240);
+
 #[repr(C)]
 #[derive(Debug, Clone)]
 pub enum Kind {
@@ -23,7 +86,58 @@ pub enum Kind {
     Write,
     Free,
     Read,
-    OpenAt
+    OpenAt,
+    Exec,
+    // Writes and reads seen at the socket: plaintext TCP, or kTLS.
+    PlainWrite,
+    PlainRead,
+    // SSL_sendfile: `len` bytes went out straight from a file, so there is no data.
+    SendFile,
+    // A connect() on the file descriptor in `handle`. The data is the struct sockaddr,
+    // padded to CONNECT_SOCK_OFFSET, then the struct sock pointer and when the connect
+    // started, if we saw that.
+    Connect,
+    // The TCP connection of the socket (struct sock pointer) in `handle` is up.
+    Connected,
+    // The socket that a TLS connection runs over, as an int in the data.
+    SetFd,
+    // The server name (SNI) that a TLS connection asks for, as a string.
+    Sni,
+    // A TLS handshake that completed, with when it started as the data.
+    Handshake,
+    // A DNS lookup, see DNS_NAME_OFFSET.
+    Dns,
+    // The socket (struct sock pointer) in `handle` of an earlier Connect got closed.
+    Closed
+}
+
+pub const CONNECT_SOCK_OFFSET: usize = 32;
+pub const CONNECT_LEN: usize = CONNECT_SOCK_OFFSET + 16;
+
+// A DNS lookup has what the lookup returned in `handle`, an EAI_* code from getaddrinfo.
+// The data is when it started, the name that got looked up and the addresses it found,
+// each in a slot the size of a struct sockaddr_in6.
+pub const DNS_NAME_OFFSET: usize = 8;
+pub const DNS_NAME_LEN: usize = 256;
+pub const DNS_ADDRS_OFFSET: usize = DNS_NAME_OFFSET + DNS_NAME_LEN;
+pub const DNS_ADDR_LEN: usize = 28;
+pub const MAX_DNS_ADDRS: usize = 8;
+
+// The TLS library that an event came from.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub enum Library {
+    OpenSsl,
+    GnuTls,
+    Nss,
+    Go,
+    Rustls,
+    // Not a probe, but our Java agent (see src/jvm.rs in the agent).
+    Jsse,
+    // No TLS at all.
+    Plain,
+    // TLS done by the kernel, for an application that set it up by itself.
+    Ktls
 }
 
 #[repr(C)]
@@ -31,6 +145,7 @@ pub enum Kind {
 pub struct TlsEvent {
     // Basic identifying data
     pub kind: Kind,
+    pub library: Library,
     pub pid: u32,
     pub tgid: u32,
     pub ts: u64,
@@ -43,6 +158,7 @@ impl Default for TlsEvent {
     fn default() -> TlsEvent {
         TlsEvent {
             kind: Kind::Unset,
+            library: Library::OpenSsl,
             pid: 0,
             tgid: 0,
             handle: 0,
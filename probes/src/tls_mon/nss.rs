@@ -0,0 +1,129 @@
+use redbpf_probes::uprobe::prelude::*;
+use probes::tls_mon::*;
+
+// Mozilla NSS, as used on RHEL and friends. Applications do their I/O through the
+// generic NSPR calls in libnspr4.so, on file descriptors that have an SSL layer
+// pushed on top of them by SSL_ImportFD in libssl3.so. NSPR is used for any kind
+// of I/O, so we remember which descriptors are SSL ones and ignore all others.
+//
+// Pushing a layer keeps the PRFileDesc pointer of the top of the stack the same, so
+// the pointer that SSL_ImportFD returns is what the application uses from then on.
+
+// All functions in here _MUST_ be the same as the library function names they probe!
+
+// PRFileDesc *SSL_ImportFD(PRFileDesc *model, PRFileDesc *fd);
+#[allow(unused_must_use, non_snake_case)]
+#[uretprobe]
+fn SSL_ImportFD(regs: Registers) {
+    unsafe {
+        let fd = regs.rc();
+        if fd == 0 {
+            return;
+        }
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+
+        event.kind = Kind::New;
+        event.library = Library::Nss;
+        event.handle = fd;
+        event.ts = bpf_ktime_get_ns();
+
+        let pid_tgid = bpf_get_current_pid_tgid();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+
+        NSS_SSL_FDS.set(&[pid_tgid >> 32, fd], &1);
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
+
+// PRInt32 PR_Write(PRFileDesc *fd, const void *buf, PRInt32 amount);
+//
+// Like GnuTLS, NSPR can return an error on non-blocking sockets, to be repeated
+// later, so we look at these on return.
+#[allow(non_snake_case)]
+#[uretprobe]
+fn PR_Write(regs: Registers, parms: [u64; 5]) {
+    unsafe { emit_data(&regs, Kind::Write, parms[0], parms[1]); }
+}
+
+// PRInt32 PR_Send(PRFileDesc *fd, const void *buf, PRInt32 amount,
+//                 PRIntn flags, PRIntervalTime timeout);
+#[allow(non_snake_case)]
+#[uretprobe]
+fn PR_Send(regs: Registers, parms: [u64; 5]) {
+    unsafe { emit_data(&regs, Kind::Write, parms[0], parms[1]); }
+}
+
+// PRInt32 PR_Read(PRFileDesc *fd, void *buf, PRInt32 amount);
+#[allow(non_snake_case)]
+#[uretprobe]
+fn PR_Read(regs: Registers, parms: [u64; 5]) {
+    unsafe { emit_data(&regs, Kind::Read, parms[0], parms[1]); }
+}
+
+// PRInt32 PR_Recv(PRFileDesc *fd, void *buf, PRInt32 amount,
+//                 PRIntn flags, PRIntervalTime timeout);
+#[allow(non_snake_case)]
+#[uretprobe]
+fn PR_Recv(regs: Registers, parms: [u64; 5]) {
+    unsafe { emit_data(&regs, Kind::Read, parms[0], parms[1]); }
+}
+
+// PRStatus PR_Close(PRFileDesc *fd);
+#[allow(unused_must_use, non_snake_case)]
+#[uprobe]
+fn PR_Close(regs: Registers) {
+    unsafe {
+        let pid_tgid = bpf_get_current_pid_tgid();
+        let key = [pid_tgid >> 32, regs.parm1()];
+        if NSS_SSL_FDS.get(&key).is_none() {
+            return;
+        }
+        NSS_SSL_FDS.delete(&key);
+
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+
+        event.kind = Kind::Free;
+        event.library = Library::Nss;
+        event.handle = regs.parm1();
+        event.ts = bpf_ktime_get_ns();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
+
+// A read or write of `rc` bytes at `buf`, if `fd` is an SSL descriptor.
+#[allow(unused_must_use)]
+#[inline(always)]
+unsafe fn emit_data(regs: &Registers, kind: Kind, fd: u64, buf: u64) {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    if NSS_SSL_FDS.get(&[pid_tgid >> 32, fd]).is_none() {
+        return;
+    }
+    let len = regs.rc() as i32;
+    if len <= 0 {
+        return;
+    }
+
+    let mut event = TMP_EVENT.get_mut(0).unwrap();
+    event.kind = kind;
+    event.library = Library::Nss;
+    event.handle = fd;
+    event.ts = bpf_ktime_get_ns();
+    event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+    event.tgid = (pid_tgid >> 32) as u32;
+
+    let err =
+        bpf_probe_read_user(
+            event.data.as_mut_ptr() as *mut _,
+            if len > (BUFSIZE as i32) { BUFSIZE as u32 } else { len as u32 },
+            buf as *const _);
+    if err < 0 {
+        printk!("error %lld on bpf_probe_read_user", err);
+    } else {
+        event.len = len as usize;
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
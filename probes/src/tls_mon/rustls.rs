@@ -0,0 +1,73 @@
+use redbpf_probes::uprobe::prelude::*;
+use probes::tls_mon::*;
+
+// rustls, for Rust programs that don't use OpenSSL. Like for Go, these are attached
+// at offsets that user mode finds in the executable.
+//
+// Applications get a short-lived Writer or Reader from the connection for every call:
+//
+//   impl Write for Writer<'_> { fn write(&mut self, buf: &[u8]) -> io::Result<usize> }
+//   impl Read for Reader<'_> { fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> }
+//
+// A Writer holds a reference to the connection and a Reader a reference to the
+// buffer with received plaintext, which lives inside the connection. Both are the
+// first thing in the struct, so that is what we use as the handle; user mode maps
+// the reader's handle back onto the connection.
+//
+// These are plain Rust functions: `self` comes in RDI and the slice in RSI and RDX.
+// The result comes back in RAX (zero for Ok) and RDX (the number of bytes).
+
+#[allow(unused_must_use)]
+#[uretprobe]
+fn rustls_write(regs: Registers, parms: [u64; 5]) {
+    unsafe { emit_data(&regs, Kind::Write, parms[0], parms[1]); }
+}
+
+#[allow(unused_must_use)]
+#[uretprobe]
+fn rustls_read(regs: Registers, parms: [u64; 5]) {
+    unsafe { emit_data(&regs, Kind::Read, parms[0], parms[1]); }
+}
+
+#[allow(unused_must_use)]
+#[inline(always)]
+unsafe fn emit_data(regs: &Registers, kind: Kind, this: u64, buf: u64) {
+    let ctx = &*regs.ctx;
+    let len = ctx.rdx as i64;
+    if ctx.rax != 0 || len <= 0 {
+        return;
+    }
+
+    let mut handle: u64 = 0;
+    let err =
+        bpf_probe_read_user(
+            &mut handle as *mut _ as *mut c_void,
+            8,
+            this as *const _);
+    if err < 0 {
+        printk!("error %lld reading rustls handle", err);
+        return;
+    }
+
+    let mut event = TMP_EVENT.get_mut(0).unwrap();
+    event.kind = kind;
+    event.library = Library::Rustls;
+    event.handle = handle;
+    event.ts = bpf_ktime_get_ns();
+
+    let pid_tgid = bpf_get_current_pid_tgid();
+    event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+    event.tgid = (pid_tgid >> 32) as u32;
+
+    let err =
+        bpf_probe_read_user(
+            event.data.as_mut_ptr() as *mut _,
+            if len > (BUFSIZE as i64) { BUFSIZE as u32 } else { len as u32 },
+            buf as *const _);
+    if err < 0 {
+        printk!("error %lld on bpf_probe_read_user", err);
+    } else {
+        event.len = len as usize;
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
@@ -0,0 +1,299 @@
+use redbpf_probes::kprobe::prelude::*;
+use probes::tls_mon::*;
+
+// Plaintext HTTP on TCP sockets. The write(), read(), sendto() and recvfrom() system
+// calls on a TCP socket all end up in tcp_sendmsg() or tcp_recvmsg(), which tell us the
+// socket. When the system call returns, we know how much got transferred and we can
+// read it from the buffer that user mode passed in.
+//
+// We only look at sockets to remote ports or in processes that user mode configured.
+// Of those, the first write decides whether a socket carries HTTP. That keeps out TLS
+// and other protocols, and also the server side of connections, as servers read first.
+//
+// Kernel TLS (kTLS) sockets, where the kernel encrypts, see the plaintext go through
+// the same system calls, so we pick those up here too; see tls_sw_sendmsg().
+//
+// These probes only get attached if plaintext or kTLS capture is configured. Functions
+// in here _MUST_ be the same as the kernel function names they probe!
+
+// The start of struct sock_common has not changed in ages: remote and local address,
+// hash, then remote and local port.
+const SKC_DPORT_OFFSET: u64 = 12;
+
+// int tls_sw_sendmsg(struct sock *sk, struct msghdr *msg, size_t size)
+// Only sockets with the TLS ULP (setsockopt(TCP_ULP, "tls")) get here, instead of in
+// tcp_sendmsg(). If the first write happens inside SSL_write, the socket is OpenSSL's
+// and the OpenSSL probes see its traffic; otherwise the application does kTLS by
+// itself and we report it on the socket.
+#[allow(unused_must_use)]
+#[kprobe]
+fn tls_sw_sendmsg(regs: Registers) {
+    unsafe {
+        let sk = regs.parm1();
+        let pid_tgid = bpf_get_current_pid_tgid();
+        let ssl = match KTLS_SOCKS.get(&sk) {
+            Some(ssl) => *ssl,
+            None => {
+                let ssl = match SSL_CALLS.get(&pid_tgid) {
+                    Some(ssl) => *ssl,
+                    None => 0,
+                };
+                KTLS_SOCKS.set(&sk, &ssl);
+                ssl
+            }
+        };
+        SSL_CALLS.delete(&pid_tgid);
+        if ssl == 0 {
+            SOCK_CALLS.set(&pid_tgid, &[sk, regs.parm3(), 1]);
+        }
+    }
+}
+
+// int tls_sw_recvmsg(struct sock *sk, struct msghdr *msg, size_t len, ...)
+#[allow(unused_must_use)]
+#[kprobe]
+fn tls_sw_recvmsg(regs: Registers) {
+    unsafe {
+        let sk = regs.parm1();
+        if let Some(ssl) = KTLS_SOCKS.get(&sk) {
+            if *ssl == 0 {
+                SOCK_CALLS.set(&bpf_get_current_pid_tgid(), &[sk, regs.parm3(), 1]);
+            }
+        }
+    }
+}
+
+// int tcp_sendmsg(struct sock *sk, struct msghdr *msg, size_t size)
+#[kprobe]
+fn tcp_sendmsg(regs: Registers) {
+    unsafe { track_call(regs.parm1(), regs.parm3()); }
+}
+
+// int tcp_recvmsg(struct sock *sk, struct msghdr *msg, size_t len, ...)
+// The arguments after `len` differ between kernel versions.
+#[kprobe]
+fn tcp_recvmsg(regs: Registers) {
+    unsafe { track_call(regs.parm1(), regs.parm3()); }
+}
+
+// ssize_t vfs_write(struct file *file, const char __user *buf, size_t count, loff_t *pos)
+#[kretprobe]
+fn vfs_write(regs: Registers, parms: [u64; 5]) {
+    unsafe { emit_data(&regs, true, parms[1], parms[2]); }
+}
+
+// ssize_t vfs_read(struct file *file, char __user *buf, size_t count, loff_t *pos)
+#[kretprobe]
+fn vfs_read(regs: Registers, parms: [u64; 5]) {
+    unsafe { emit_data(&regs, false, parms[1], parms[2]); }
+}
+
+// int __sys_sendto(int fd, void __user *buff, size_t len, unsigned int flags,
+//                  struct sockaddr __user *addr,  int addr_len)
+#[kretprobe]
+fn __sys_sendto(regs: Registers, parms: [u64; 5]) {
+    unsafe { emit_data(&regs, true, parms[1], parms[2]); }
+}
+
+// int __sys_recvfrom(int fd, void __user *ubuf, size_t size, unsigned int flags,
+//                    struct sockaddr __user *addr, int __user *addr_len)
+#[kretprobe]
+fn __sys_recvfrom(regs: Registers, parms: [u64; 5]) {
+    unsafe { emit_data(&regs, false, parms[1], parms[2]); }
+}
+
+// Other system calls that end up in tcp_sendmsg() or tcp_recvmsg(). We don't capture
+// what they transfer, but we must not leave their call behind for the next system call
+// of the thread to pick up.
+
+// ssize_t do_writev(unsigned long fd, const struct iovec __user *vec,
+//                   unsigned long vlen, rwf_t flags)
+#[kretprobe]
+fn do_writev(_regs: Registers) {
+    unsafe { forget_call(); }
+}
+
+// ssize_t do_readv(unsigned long fd, const struct iovec __user *vec,
+//                  unsigned long vlen, rwf_t flags)
+#[kretprobe]
+fn do_readv(_regs: Registers) {
+    unsafe { forget_call(); }
+}
+
+// long __sys_sendmsg(int fd, struct user_msghdr __user *msg, unsigned int flags, ...)
+#[kretprobe]
+fn __sys_sendmsg(_regs: Registers) {
+    unsafe { forget_call(); }
+}
+
+// long __sys_recvmsg(int fd, struct user_msghdr __user *msg, unsigned int flags, ...)
+#[kretprobe]
+fn __sys_recvmsg(_regs: Registers) {
+    unsafe { forget_call(); }
+}
+
+// ssize_t do_sendfile(int out_fd, int in_fd, loff_t *ppos, size_t count, loff_t max)
+#[kretprobe]
+fn do_sendfile(_regs: Registers) {
+    unsafe { forget_call(); }
+}
+
+// void tcp_close(struct sock *sk, long timeout)
+// Unlike the other probes in here, this one is always attached: file descriptors get
+// reused, so user mode needs to know when a socket that it saw connected goes away.
+#[allow(unused_must_use)]
+#[kprobe]
+fn tcp_close(regs: Registers) {
+    unsafe {
+        let sk = regs.parm1();
+        if CONNECTED_SOCKS.get(&sk).is_some() {
+            CONNECTED_SOCKS.delete(&sk);
+
+            let mut event = TMP_EVENT.get_mut(0).unwrap();
+            event.kind = Kind::Closed;
+            event.handle = sk;
+            event.ts = bpf_ktime_get_ns();
+
+            let pid_tgid = bpf_get_current_pid_tgid();
+            event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+            event.tgid = (pid_tgid >> 32) as u32;
+            event.len = 0;
+
+            TLS_BUF.insert(regs.ctx, &event);
+        }
+
+        KTLS_SOCKS.delete(&sk);
+        let is_http = match PLAIN_SOCKS.get(&sk) {
+            Some(is_http) => *is_http,
+            None => return,
+        };
+        PLAIN_SOCKS.delete(&sk);
+        if is_http == 0 {
+            return;
+        }
+
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+        event.kind = Kind::Free;
+        event.library = Library::Plain;
+        event.handle = sk;
+        event.ts = bpf_ktime_get_ns();
+
+        let pid_tgid = bpf_get_current_pid_tgid();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
+
+// Remember the socket that this thread's system call is for, if we want it.
+#[allow(unused_must_use)]
+#[inline(always)]
+unsafe fn track_call(sk: u64, size: u64) {
+    match PLAIN_SOCKS.get(&sk) {
+        Some(is_http) if *is_http == 0 => return,
+        Some(_) => {}
+        None => {
+            if !is_of_interest(sk) {
+                return;
+            }
+        }
+    }
+    SOCK_CALLS.set(&bpf_get_current_pid_tgid(), &[sk, size, 0]);
+}
+
+#[allow(unused_must_use)]
+#[inline(always)]
+unsafe fn forget_call() {
+    SOCK_CALLS.delete(&bpf_get_current_pid_tgid());
+}
+
+#[inline(always)]
+unsafe fn is_of_interest(sk: u64) -> bool {
+    let mut dport: u16 = 0;
+    bpf_probe_read_kernel(
+        &mut dport as *mut u16 as *mut _,
+        2,
+        (sk + SKC_DPORT_OFFSET) as *const _);
+    // Ports are kept in network byte order.
+    if PLAIN_PORTS.get(&u16::from_be(dport)).is_some() {
+        return true;
+    }
+    let comm: [u8; 16] = core::mem::transmute(bpf_get_current_comm());
+    PLAIN_PROCESSES.get(&comm).is_some()
+}
+
+// The system call returned; if it was for a socket that we track, we send out the
+// data. `count` is what the call was asked to transfer, which is also what the TCP
+// function got; this makes sure that we match up the right calls.
+#[allow(unused_must_use)]
+#[inline(always)]
+unsafe fn emit_data(regs: &Registers, is_write: bool, buf: u64, count: u64) {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let call = match SOCK_CALLS.get(&pid_tgid) {
+        Some(call) => *call,
+        None => return,
+    };
+    SOCK_CALLS.delete(&pid_tgid);
+    if call[1] != count {
+        return;
+    }
+    let sk = call[0];
+    let len = regs.rc() as i64;
+    if len <= 0 {
+        return;
+    }
+
+    let mut event = TMP_EVENT.get_mut(0).unwrap();
+    let err =
+        bpf_probe_read_user(
+            event.data.as_mut_ptr() as *mut _,
+            if len > (BUFSIZE as i64) { BUFSIZE as u32 } else { len as u32 },
+            buf as *const _);
+    if err < 0 {
+        printk!("error %lld on bpf_probe_read_user", err);
+        return;
+    }
+
+    match PLAIN_SOCKS.get(&sk) {
+        Some(is_http) if *is_http == 0 => return,
+        Some(_) => {}
+        None => {
+            if !is_write {
+                return;
+            }
+            let is_http = if len >= 4 && is_http_start(&event.data) { 1 } else { 0 };
+            PLAIN_SOCKS.set(&sk, &is_http);
+            if is_http == 0 {
+                return;
+            }
+        }
+    }
+
+    event.kind = if is_write { Kind::PlainWrite } else { Kind::PlainRead };
+    event.library = if call[2] == 1 { Library::Ktls } else { Library::Plain };
+    event.handle = sk;
+    event.ts = bpf_ktime_get_ns();
+    event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+    event.tgid = (pid_tgid >> 32) as u32;
+    event.len = len as usize;
+    TLS_BUF.insert(regs.ctx, &event);
+}
+
+// An HTTP/1.x request, or the HTTP/2 connection preface ("PRI * HTTP/2.0...") that
+// clients with prior knowledge start h2c with. The methods are those of is_method() in
+// the agent's http1.rs, so plaintext and TLS agree on what is HTTP.
+#[inline(always)]
+fn is_http_start(data: &[u8; BUFSIZE]) -> bool {
+    let start = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+    start == u32::from_be_bytes(*b"GET ") ||
+        start == u32::from_be_bytes(*b"POST") ||
+        start == u32::from_be_bytes(*b"PUT ") ||
+        start == u32::from_be_bytes(*b"HEAD") ||
+        start == u32::from_be_bytes(*b"DELE") ||
+        start == u32::from_be_bytes(*b"PATC") ||
+        start == u32::from_be_bytes(*b"OPTI") ||
+        start == u32::from_be_bytes(*b"CONN") ||
+        start == u32::from_be_bytes(*b"TRAC") ||
+        start == u32::from_be_bytes(*b"PRI ")
+}
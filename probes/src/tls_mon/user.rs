@@ -2,22 +2,32 @@ use redbpf_probes::uprobe::prelude::*;
 use probes::tls_mon::*;
 
 // Note that we also have SSL_write_ex and (yay, fun) SSL_sendfile to contend with.
-// SSL_sendfile requires kernel mode TLS and I don't think that that is very common. Don't
-// know about SSL_write_ex and SSL_read_ex.
+// SSL_sendfile requires kernel mode TLS and sends straight from a file, so all we can
+// report for it is how much got sent.
+//
+// With kernel mode TLS, OpenSSL still gets called with the plaintext, so these probes
+// work as usual. SSL_write tells the kernel probes (see kernel TLS in socket.rs) that
+// the socket is OpenSSL's, so they leave it alone.
 
-// Also note that with these three functions, we don't measure socket connect() time,
-// which can be long to very long. Given that the focus is on discovery for now,
-// that is not yet a major issue.
+// Also note that these functions don't tell us how long the socket connect() took,
+// which can be long to very long. The kernel probes (see kernel.rs) time that, and we
+// time the handshake here, through SSL_do_handshake and SSL_connect.
+
+// Where a connection goes comes from SSL_set_fd, which tells us the socket (the kernel
+// probe on connect() has its address), and from the server name that gets set for SNI.
 
 // All functions in here _MUST_ be the same as the library function names they probe!
 
 // int SSL_write(SSL *ssl, const void *buf, size_t num)
+// We also probe the return of the write functions, for SSL_CALLS. Probes on the start
+// of a function are named after it with "_enter" added.
 #[allow(unused_must_use, non_snake_case)]
 #[uprobe]
-fn SSL_write(regs: Registers) {
+fn SSL_write_enter(regs: Registers) {
     unsafe {
         let mut event = TMP_EVENT.get_mut(0).unwrap();
         event.kind = Kind::Write;
+        event.library = Library::OpenSsl;
         event.handle = regs.parm1();
         event.ts = bpf_ktime_get_ns();
 
@@ -25,6 +35,8 @@ fn SSL_write(regs: Registers) {
         event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
         event.tgid = (pid_tgid >> 32) as u32;
 
+        SSL_CALLS.set(&pid_tgid, &regs.parm1());
+
         let data = regs.parm2() as *const u8;
         let len = regs.parm3() as i64;
         let err =
@@ -44,10 +56,11 @@ fn SSL_write(regs: Registers) {
 // int SSL_write_ex(SSL *s, const void *buf, size_t num, size_t *written);
 #[allow(unused_must_use, non_snake_case)]
 #[uprobe]
-fn SSL_write_ex(regs: Registers) {
+fn SSL_write_ex_enter(regs: Registers) {
     unsafe {
         let mut event = TMP_EVENT.get_mut(0).unwrap();
         event.kind = Kind::Write;
+        event.library = Library::OpenSsl;
         event.handle = regs.parm1();
         event.ts = bpf_ktime_get_ns();
 
@@ -55,6 +68,8 @@ fn SSL_write_ex(regs: Registers) {
         event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
         event.tgid = (pid_tgid >> 32) as u32;
 
+        SSL_CALLS.set(&pid_tgid, &regs.parm1());
+
         let data = regs.parm2() as *const u8;
         let len = regs.parm3() as i64;
         let err =
@@ -71,6 +86,19 @@ fn SSL_write_ex(regs: Registers) {
     }
 }
 
+// The socket writes of the call are done by now.
+#[allow(unused_must_use, non_snake_case)]
+#[uretprobe]
+fn SSL_write(_regs: Registers) {
+    unsafe { SSL_CALLS.delete(&bpf_get_current_pid_tgid()); }
+}
+
+#[allow(unused_must_use, non_snake_case)]
+#[uretprobe]
+fn SSL_write_ex(_regs: Registers) {
+    unsafe { SSL_CALLS.delete(&bpf_get_current_pid_tgid()); }
+}
+
 //  int SSL_read(SSL *ssl, void *buf, int num);
 #[allow(unused_must_use, non_snake_case)]
 #[uretprobe]
@@ -78,6 +106,7 @@ fn SSL_read(regs: Registers, parms: [u64; 5]) {
     unsafe {
         let mut event = TMP_EVENT.get_mut(0).unwrap();
         event.kind = Kind::Read;
+        event.library = Library::OpenSsl;
         event.handle = parms[0];
         event.ts = bpf_ktime_get_ns();
 
@@ -110,6 +139,7 @@ fn SSL_read_ex(regs: Registers, parms: [u64; 5]) {
     unsafe {
         let mut event = TMP_EVENT.get_mut(0).unwrap();
         event.kind = Kind::Read;
+        event.library = Library::OpenSsl;
         event.handle = parms[0];
         event.ts = bpf_ktime_get_ns();
 
@@ -146,6 +176,173 @@ fn SSL_read_ex(regs: Registers, parms: [u64; 5]) {
     }
 }
 
+// ossl_ssize_t SSL_sendfile(SSL *s, int fd, off_t offset, size_t size, int flags);
+#[allow(unused_must_use, non_snake_case)]
+#[uretprobe]
+fn SSL_sendfile(regs: Registers, parms: [u64; 5]) {
+    unsafe {
+        let len = regs.rc() as i64;
+        if len <= 0 {
+            return;
+        }
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+        event.kind = Kind::SendFile;
+        event.library = Library::OpenSsl;
+        event.handle = parms[0];
+        event.ts = bpf_ktime_get_ns();
+
+        let pid_tgid = bpf_get_current_pid_tgid();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+        event.len = len as usize;
+
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
+
+// int SSL_set_fd(SSL *s, int fd);
+#[allow(unused_must_use, non_snake_case)]
+#[uprobe]
+fn SSL_set_fd(regs: Registers) {
+    unsafe {
+        let mut event = TMP_EVENT.get_mut(0).unwrap();
+        event.kind = Kind::SetFd;
+        event.library = Library::OpenSsl;
+        event.handle = regs.parm1();
+        event.ts = bpf_ktime_get_ns();
+
+        let pid_tgid = bpf_get_current_pid_tgid();
+        event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+        event.tgid = (pid_tgid >> 32) as u32;
+
+        let fd = regs.parm2() as u32;
+        event.data[0..4].copy_from_slice(&fd.to_ne_bytes());
+        event.len = 4;
+
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
+
+// SSL_set_tlsext_host_name(s, name) is a macro for
+// SSL_ctrl(s, SSL_CTRL_SET_TLSEXT_HOSTNAME, TLSEXT_NAMETYPE_host_name, name).
+const SSL_CTRL_SET_TLSEXT_HOSTNAME: u64 = 55;
+
+// long SSL_ctrl(SSL *ssl, int cmd, long larg, void *parg);
+#[allow(non_snake_case)]
+#[uprobe]
+fn SSL_ctrl(regs: Registers) {
+    if regs.parm2() as u32 as u64 != SSL_CTRL_SET_TLSEXT_HOSTNAME || regs.parm4() == 0 {
+        return;
+    }
+    unsafe { report_sni(&regs, regs.parm1(), regs.parm4()); }
+}
+
+// int SSL_set_tlsext_host_name(SSL *ssl, const char *name);
+// BoringSSL has this as a function of its own, which does not call SSL_ctrl.
+#[allow(non_snake_case)]
+#[uprobe]
+fn SSL_set_tlsext_host_name(regs: Registers) {
+    if regs.parm2() == 0 {
+        return;
+    }
+    unsafe { report_sni(&regs, regs.parm1(), regs.parm2()); }
+}
+
+#[allow(unused_must_use)]
+#[inline(always)]
+unsafe fn report_sni(regs: &Registers, ssl: u64, name: u64) {
+    let mut event = TMP_EVENT.get_mut(0).unwrap();
+    event.kind = Kind::Sni;
+    event.library = Library::OpenSsl;
+    event.handle = ssl;
+    event.ts = bpf_ktime_get_ns();
+
+    let pid_tgid = bpf_get_current_pid_tgid();
+    event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+    event.tgid = (pid_tgid >> 32) as u32;
+
+    // Host names are at most 255 bytes.
+    let err_or_len =
+        bpf_probe_read_user_str(
+            event.data.as_mut_ptr() as *mut _,
+            256,
+            name as *const _);
+    if err_or_len < 0 {
+        printk!("error %lld on bpf_probe_read_user_str", err_or_len);
+    } else {
+        event.len = err_or_len as usize;
+        TLS_BUF.insert(regs.ctx, &event);
+    }
+}
+
+// int SSL_do_handshake(SSL *s);
+// int SSL_connect(SSL *ssl);
+// With non-blocking sockets, these get called until the handshake is done, so it starts
+// with the first call and ends with the one that returns 1. SSL_connect calls
+// SSL_do_handshake, and whichever returns first reports it.
+//
+// We need to see both the start and the end of these. Probes on the start of a function
+// are named after it with "_enter" added.
+#[allow(non_snake_case)]
+#[uprobe]
+fn SSL_do_handshake_enter(regs: Registers) {
+    unsafe { start_handshake(regs.parm1()); }
+}
+
+#[allow(non_snake_case)]
+#[uretprobe]
+fn SSL_do_handshake(regs: Registers, parms: [u64; 5]) {
+    unsafe { end_handshake(&regs, parms[0]); }
+}
+
+#[allow(non_snake_case)]
+#[uprobe]
+fn SSL_connect_enter(regs: Registers) {
+    unsafe { start_handshake(regs.parm1()); }
+}
+
+#[allow(non_snake_case)]
+#[uretprobe]
+fn SSL_connect(regs: Registers, parms: [u64; 5]) {
+    unsafe { end_handshake(&regs, parms[0]); }
+}
+
+#[allow(unused_must_use)]
+#[inline(always)]
+unsafe fn start_handshake(ssl: u64) {
+    if HANDSHAKES.get(&ssl).is_none() {
+        HANDSHAKES.set(&ssl, &bpf_ktime_get_ns());
+    }
+}
+
+#[allow(unused_must_use)]
+#[inline(always)]
+unsafe fn end_handshake(regs: &Registers, ssl: u64) {
+    if regs.rc() as i32 != 1 {
+        return;
+    }
+    let start = match HANDSHAKES.get(&ssl) {
+        Some(start) => *start,
+        None => return,
+    };
+    HANDSHAKES.delete(&ssl);
+
+    let mut event = TMP_EVENT.get_mut(0).unwrap();
+    event.kind = Kind::Handshake;
+    event.library = Library::OpenSsl;
+    event.handle = ssl;
+    event.ts = bpf_ktime_get_ns();
+
+    let pid_tgid = bpf_get_current_pid_tgid();
+    event.pid = (pid_tgid & 0xFFFFFFFF) as u32;
+    event.tgid = (pid_tgid >> 32) as u32;
+
+    event.data[0..8].copy_from_slice(&start.to_ne_bytes());
+    event.len = 8;
+
+    TLS_BUF.insert(regs.ctx, &event);
+}
+
 #[allow(non_snake_case)]
 #[uretprobe]
 fn SSL_new(regs: Registers) {
@@ -153,6 +350,7 @@ fn SSL_new(regs: Registers) {
         let mut event = TMP_EVENT.get_mut(0).unwrap();
 
         event.kind = Kind::New;
+        event.library = Library::OpenSsl;
         event.ts = bpf_ktime_get_ns();
         event.handle = regs.rc();
 
@@ -164,14 +362,16 @@ fn SSL_new(regs: Registers) {
     }
 }
 
-#[allow(non_snake_case)]
+#[allow(unused_must_use, non_snake_case)]
 #[uprobe]
 fn SSL_free(regs: Registers) {
     unsafe {
         let mut event = TMP_EVENT.get_mut(0).unwrap();
 
         event.kind = Kind::Free;
+        event.library = Library::OpenSsl;
         event.handle = regs.parm1();
+        HANDSHAKES.delete(&event.handle);
         event.ts = bpf_ktime_get_ns();
 
         let pid_tgid = bpf_get_current_pid_tgid();
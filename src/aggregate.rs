@@ -0,0 +1,195 @@
+// Local aggregation of transactions.
+//
+// On a busy machine, sending every single transaction to Orchestrator is a lot
+// of traffic. When aggregation is enabled, we group transactions by method, host,
+// path and status over a window and only send counts, sums and latency percentiles
+// per group when the window closes. DNS lookups get grouped the same way, by name
+// and status.
+use crate::record;
+use crate::record::AggregateRecord;
+use crate::record::DnsAggregateRecord;
+use crate::record::DnsRecord;
+use crate::record::Latency;
+use crate::record::Record;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+// A bound on memory use when paths are very diverse. Anything beyond this
+// ends up in a single group per method, host and status.
+const MAX_GROUPS: usize = 10000;
+const OVERFLOW_PATH: &str = "/(overflow)";
+const OVERFLOW_NAME: &str = "(overflow)";
+
+// Latency buckets are on a log scale, with this many buckets per power of two,
+// starting at one microsecond. That keeps percentiles within about 5%.
+const BUCKETS_PER_OCTAVE: f64 = 8.0;
+
+#[derive(Hash, PartialEq, Eq)]
+struct Key {
+    method: String,
+    host: String,
+    path: String,
+    status: u16,
+}
+
+#[derive(Default)]
+struct Group {
+    count: u64,
+    request_bytes: u64,
+    response_bytes: u64,
+    latency: Histogram,
+}
+
+#[derive(Hash, PartialEq, Eq)]
+struct DnsKey {
+    name: String,
+    status: String,
+}
+
+#[derive(Default)]
+struct DnsGroup {
+    count: u64,
+    latency: Histogram,
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: BTreeMap<u32, u64>,
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+pub struct Aggregator {
+    groups: HashMap<Key, Group>,
+    dns_groups: HashMap<DnsKey, DnsGroup>,
+    window_start: u64,
+}
+
+impl Aggregator {
+    pub fn new() -> Aggregator {
+        Aggregator {
+            groups: HashMap::new(),
+            dns_groups: HashMap::new(),
+            window_start: record::now_ms(),
+        }
+    }
+
+    pub fn add(&mut self, record: &Record) {
+        let mut key = Key {
+            method: record.method.to_string(),
+            host: record.host.to_string(),
+            path: record.path.split('?').next().unwrap_or("").to_string(),
+            status: record.status,
+        };
+        if self.groups.len() >= MAX_GROUPS && !self.groups.contains_key(&key) {
+            key.path = OVERFLOW_PATH.to_string();
+        }
+        let group = self.groups.entry(key).or_default();
+        group.count += 1;
+        group.request_bytes += record.request_bytes as u64;
+        group.response_bytes += record.response_bytes as u64;
+        group.latency.add(record.duration_ms);
+    }
+
+    pub fn add_dns(&mut self, record: &DnsRecord) {
+        let mut key = DnsKey {
+            name: record.name.to_string(),
+            status: record.status.to_string(),
+        };
+        if self.dns_groups.len() >= MAX_GROUPS && !self.dns_groups.contains_key(&key) {
+            key.name = OVERFLOW_NAME.to_string();
+        }
+        let group = self.dns_groups.entry(key).or_default();
+        group.count += 1;
+        group.latency.add(record.duration_ms);
+    }
+
+    // Close the current window and hand out a record per group, encoded.
+    pub fn flush<F>(&mut self, mut emit: F)
+    where
+        F: FnMut(String),
+    {
+        let now = record::now_ms();
+        let window_ms = now.saturating_sub(self.window_start);
+        self.window_start = now;
+        for (key, group) in self.groups.drain() {
+            let aggregate = AggregateRecord {
+                version: record::RECORD_VERSION,
+                kind: "aggregate",
+                time: now,
+                window_ms,
+                method: &key.method,
+                host: &key.host,
+                path: &key.path,
+                status: key.status,
+                count: group.count,
+                request_bytes: group.request_bytes,
+                response_bytes: group.response_bytes,
+                duration_ms: group.latency.latency(),
+            };
+            emit(aggregate.encode());
+        }
+        for (key, group) in self.dns_groups.drain() {
+            let aggregate = DnsAggregateRecord {
+                version: record::RECORD_VERSION,
+                kind: "dns_aggregate",
+                time: now,
+                window_ms,
+                name: &key.name,
+                status: &key.status,
+                count: group.count,
+                duration_ms: group.latency.latency(),
+            };
+            emit(aggregate.encode());
+        }
+    }
+}
+
+impl Histogram {
+    fn add(&mut self, ms: f64) {
+        if self.count == 0 || ms < self.min {
+            self.min = ms;
+        }
+        if self.count == 0 || ms > self.max {
+            self.max = ms;
+        }
+        self.count += 1;
+        self.sum += ms;
+        *self.buckets.entry(bucket(ms)).or_insert(0) += 1;
+    }
+
+    fn latency(&self) -> Latency {
+        Latency {
+            sum: self.sum,
+            min: self.min,
+            max: self.max,
+            p50: self.percentile(0.50),
+            p90: self.percentile(0.90),
+            p99: self.percentile(0.99),
+        }
+    }
+
+    fn percentile(&self, p: f64) -> f64 {
+        let rank = ((self.count as f64) * p).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, bucket_count) in &self.buckets {
+            seen += bucket_count;
+            if seen >= rank {
+                return bucket_value(*bucket).max(self.min).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+fn bucket(ms: f64) -> u32 {
+    let us = (ms * 1000.0).max(1.0);
+    (us.log2() * BUCKETS_PER_OCTAVE).floor() as u32
+}
+
+// The middle of a bucket, on the log scale.
+fn bucket_value(bucket: u32) -> f64 {
+    ((bucket as f64 + 0.5) / BUCKETS_PER_OCTAVE).exp2() / 1000.0
+}
@@ -0,0 +1,296 @@
+// Which container, and which Kubernetes pod, a process runs in.
+//
+// The cgroup of a process tells us its container: container runtimes put every
+// container in a cgroup that is named after its id, and on Kubernetes the cgroup of the
+// pod has the pod's uid in it. For the pod's name, namespace and labels, we ask the
+// container runtime (see cri.rs) every so often.
+use crate::cri;
+use crate::cri::Pod;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::sync::Notify;
+
+// Where the usual container runtimes have their CRI socket.
+const CRI_SOCKETS: [&str; 4] = [
+    "/run/containerd/containerd.sock",
+    "/var/run/crio/crio.sock",
+    "/run/k3s/containerd/containerd.sock",
+    "/var/run/cri-dockerd.sock",
+];
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+// New pods make us ask sooner, but not more often than this.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct Container {
+    pub cgroup: String,
+    // Empty if the process does not run in a container.
+    pub id: String,
+    pub runtime: &'static str,
+    pub pod_uid: String,
+    // Where to look up the pod, see pod().
+    containers: Containers,
+}
+
+// What we know about pods, shared with the task that keeps it up to date.
+#[derive(Clone)]
+pub struct Containers {
+    pods: Arc<RwLock<HashMap<String, Arc<Pod>>>>,
+    refresh: Arc<Notify>,
+    has_cri: bool,
+}
+
+// `cri_socket` overrides where we look for the container runtime.
+pub fn start_containers(cri_socket: Option<PathBuf>) -> Containers {
+    let socket = cri_socket.or_else(|| {
+        CRI_SOCKETS
+            .iter()
+            .map(PathBuf::from)
+            .find(|socket| socket.exists())
+    });
+    let containers = Containers {
+        pods: Arc::new(RwLock::new(HashMap::new())),
+        refresh: Arc::new(Notify::new()),
+        has_cri: socket.is_some(),
+    };
+    if let Some(socket) = socket {
+        println!("Getting pods from {}.", socket.display());
+        let pods = containers.pods.clone();
+        let refresh = containers.refresh.clone();
+        tokio::spawn(async move {
+            let mut last_err = String::new();
+            loop {
+                let started = Instant::now();
+                match cri::list_pods(&socket).await {
+                    Ok(listed) => {
+                        *pods.write().unwrap() = listed;
+                        last_err.clear();
+                    }
+                    Err(err) => {
+                        // Once is enough, the runtime may well be gone for a while.
+                        if err != last_err {
+                            println!("Cannot list pods: {}", err);
+                            last_err = err;
+                        }
+                    }
+                }
+                tokio::time::timeout(REFRESH_INTERVAL, refresh.notified())
+                    .await
+                    .ok();
+                let elapsed = started.elapsed();
+                if elapsed < MIN_REFRESH_INTERVAL {
+                    tokio::time::sleep(MIN_REFRESH_INTERVAL - elapsed).await;
+                }
+            }
+        });
+    }
+    containers
+}
+
+impl Containers {
+    // None if the process is gone.
+    pub fn for_process(&self, pid: u32) -> Option<Container> {
+        let cgroups = fs::read_to_string(format!("/proc/{}/cgroup", pid)).ok()?;
+        let cgroup = pick_cgroup(&cgroups)?;
+        let (id, runtime) = container_id(&cgroup).unwrap_or_default();
+        let pod_uid = pod_uid(&cgroup).unwrap_or_default();
+        let is_new_pod =
+            !id.is_empty() && !pod_uid.is_empty() && !self.pods.read().unwrap().contains_key(&id);
+        if is_new_pod && self.has_cri {
+            // A pod that started since we last asked.
+            self.refresh.notify_one();
+        }
+        Some(Container {
+            cgroup,
+            id,
+            runtime,
+            pod_uid,
+            containers: self.clone(),
+        })
+    }
+}
+
+impl Container {
+    // We look the pod up when we report on the container, rather than when we first
+    // see it: by then we will have heard about pods that just started, and pods that
+    // are gone.
+    pub fn pod(&self) -> Option<Arc<Pod>> {
+        if self.id.is_empty() {
+            return None;
+        }
+        self.containers.pods.read().unwrap().get(&self.id).cloned()
+    }
+}
+
+// Lines are "hierarchy-id:controllers:path". With cgroup v2 there is a single line,
+// "0::/path". With v1, we take the first path that has a container in it, or else
+// the one that systemd uses.
+fn pick_cgroup(cgroups: &str) -> Option<String> {
+    let paths = cgroups
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, ':');
+            let _ = parts.next()?;
+            Some((parts.next()?, parts.next()?))
+        })
+        .collect::<Vec<(&str, &str)>>();
+    paths
+        .iter()
+        .find(|(_, path)| container_id(path).is_some())
+        .or_else(|| paths.iter().find(|(controllers, _)| controllers.is_empty()))
+        .or_else(|| {
+            paths
+                .iter()
+                .find(|(controllers, _)| *controllers == "name=systemd")
+        })
+        .or_else(|| paths.first())
+        .map(|(_, path)| path.to_string())
+}
+
+// The container id is the last part of the path, either as is (cgroupfs) or with
+// the runtime in front of it (systemd), like
+//   /docker/<id>
+//   /system.slice/docker-<id>.scope
+//   /kubepods/burstable/pod<uid>/<id>
+//   /kubepods.slice/.../cri-containerd-<id>.scope
+//   /kubepods.slice/.../crio-<id>.scope
+fn container_id(cgroup: &str) -> Option<(String, &'static str)> {
+    let last = cgroup.rsplit('/').next()?;
+    let last = last.strip_suffix(".scope").unwrap_or(last);
+    let (prefix, id) = match last.rsplit_once('-') {
+        Some((prefix, id)) => (prefix, id),
+        None => ("", last),
+    };
+    if id.len() != 64 || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let runtime = match prefix {
+        "docker" => "docker",
+        "cri-containerd" => "containerd",
+        "crio" => "cri-o",
+        "libpod" => "podman",
+        _ if cgroup.contains("/docker/") => "docker",
+        _ => "",
+    };
+    Some((id.to_string(), runtime))
+}
+
+// "pod<uid>" with cgroupfs, "kubepods-burstable-pod<uid>.slice" with systemd, which has
+// underscores instead of the dashes in the uid.
+fn pod_uid(cgroup: &str) -> Option<String> {
+    if !cgroup.contains("kubepods") {
+        return None;
+    }
+    cgroup.split('/').find_map(|part| {
+        let part = part.strip_suffix(".slice").unwrap_or(part);
+        let uid = match part.strip_prefix("pod") {
+            Some(uid) => uid,
+            None => part.rsplit('-').next()?.strip_prefix("pod")?,
+        };
+        if uid.len() < 32 {
+            return None;
+        }
+        Some(uid.replace('_', "-"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ID: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    #[test]
+    fn container_ids() {
+        let id = |cgroup: &str| container_id(cgroup).map(|(id, runtime)| (id == ID, runtime));
+        assert_eq!(id(&format!("/docker/{}", ID)), Some((true, "docker")));
+        assert_eq!(
+            id(&format!("/system.slice/docker-{}.scope", ID)),
+            Some((true, "docker"))
+        );
+        assert_eq!(
+            id(&format!("/kubepods/burstable/pod1234/{}", ID)),
+            Some((true, ""))
+        );
+        assert_eq!(
+            id(&format!(
+                "/kubepods.slice/kubepods-pod1234.slice/cri-containerd-{}.scope",
+                ID
+            )),
+            Some((true, "containerd"))
+        );
+        assert_eq!(
+            id(&format!("/kubepods.slice/crio-{}.scope", ID)),
+            Some((true, "cri-o"))
+        );
+        assert_eq!(
+            id(&format!("/machine.slice/libpod-{}.scope", ID)),
+            Some((true, "podman"))
+        );
+    }
+
+    #[test]
+    fn not_containers() {
+        assert_eq!(container_id("/system.slice/nginx.service"), None);
+        assert_eq!(
+            container_id("/user.slice/user-1000.slice/session-2.scope"),
+            None
+        );
+        assert_eq!(container_id(&format!("/docker/{}", &ID[1..])), None);
+        assert_eq!(container_id(&format!("/docker/{}x", &ID[1..])), None);
+        assert_eq!(container_id("/"), None);
+    }
+
+    #[test]
+    fn pod_uids() {
+        let uid = "5f0ae8c6-2a3b-4c5d-8e9f-0a1b2c3d4e5f";
+        assert_eq!(
+            pod_uid(&format!("/kubepods/burstable/pod{}/{}", uid, ID)).as_deref(),
+            Some(uid)
+        );
+        assert_eq!(
+            pod_uid(&format!(
+                "/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod{}.slice/cri-containerd-{}.scope",
+                uid.replace('-', "_"),
+                ID
+            ))
+            .as_deref(),
+            Some(uid)
+        );
+        assert_eq!(
+            pod_uid(&format!(
+                "/kubepods.slice/kubepods-pod{}.slice",
+                uid.replace('-', "_")
+            ))
+            .as_deref(),
+            Some(uid)
+        );
+        assert_eq!(pod_uid(&format!("/docker/{}", ID)), None);
+        assert_eq!(pod_uid("/kubepods/burstable/pod1234"), None);
+    }
+
+    #[test]
+    fn picks_container_cgroup() {
+        let v1 = format!(
+            "12:pids:/system.slice/containerd.service\n\
+             11:memory:/docker/{}\n\
+             1:name=systemd:/system.slice/containerd.service\n",
+            ID
+        );
+        assert_eq!(pick_cgroup(&v1), Some(format!("/docker/{}", ID)));
+        let v1_host = "3:cpu:/\n1:name=systemd:/system.slice/nginx.service\n";
+        assert_eq!(
+            pick_cgroup(v1_host).as_deref(),
+            Some("/system.slice/nginx.service")
+        );
+        assert_eq!(
+            pick_cgroup("0::/system.slice/nginx.service\n").as_deref(),
+            Some("/system.slice/nginx.service")
+        );
+    }
+}
@@ -0,0 +1,308 @@
+// Asking the container runtime which pods run on this node.
+//
+// Kubernetes talks to containerd, CRI-O etc. through the Container Runtime Interface,
+// gRPC on a Unix socket. We ask it for the pod sandboxes, which have the pod's name,
+// namespace and labels, and for the containers, which tell us which sandbox (pod) they
+// are in. We only need a handful of fields, so we decode the protobuf messages by hand.
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UnixStream;
+
+// Runtimes that have moved on to v1 may not have v1alpha2 anymore, and the other
+// way around. The messages that we use are the same in both.
+const SERVICES: [&str; 2] = [
+    "runtime.v1.RuntimeService",
+    "runtime.v1alpha2.RuntimeService",
+];
+
+const CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+pub struct Pod {
+    pub name: String,
+    pub namespace: String,
+    pub uid: String,
+    pub labels: BTreeMap<String, String>,
+}
+
+// The pods on this node, by the ids of their containers (including the sandbox's).
+pub async fn list_pods(socket: &Path) -> Result<HashMap<String, Arc<Pod>>, String> {
+    let mut last_err = String::new();
+    for service in SERVICES.iter() {
+        match list_pods_from(socket, service).await {
+            Ok(pods) => return Ok(pods),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+async fn list_pods_from(socket: &Path, service: &str) -> Result<HashMap<String, Arc<Pod>>, String> {
+    // Both requests are empty messages: no filter, everything.
+    let sandboxes = call(socket, &format!("/{}/ListPodSandbox", service)).await?;
+    let containers = call(socket, &format!("/{}/ListContainers", service)).await?;
+
+    let mut pods = HashMap::new();
+    // ListPodSandboxResponse: repeated PodSandbox items = 1
+    for (_, sandbox) in fields(&sandboxes).filter(|(field, _)| *field == 1) {
+        if let Some((id, pod)) = sandbox.bytes().and_then(parse_sandbox) {
+            pods.insert(id, Arc::new(pod));
+        }
+    }
+    // ListContainersResponse: repeated Container containers = 1
+    let mut by_container = HashMap::new();
+    for (_, container) in fields(&containers).filter(|(field, _)| *field == 1) {
+        // Container: string id = 1, string pod_sandbox_id = 2
+        let container = match container.bytes() {
+            Some(container) => container,
+            None => continue,
+        };
+        let id = string_field(container, 1);
+        let sandbox_id = string_field(container, 2);
+        if let Some(pod) = pods.get(&sandbox_id) {
+            by_container.insert(id, pod.clone());
+        }
+    }
+    by_container.extend(pods);
+    Ok(by_container)
+}
+
+// PodSandbox: string id = 1, PodSandboxMetadata metadata = 2,
+// map<string, string> labels = 5
+// PodSandboxMetadata: string name = 1, string uid = 2, string namespace = 3
+fn parse_sandbox(sandbox: &[u8]) -> Option<(String, Pod)> {
+    let mut id = String::new();
+    let mut pod = Pod::default();
+    for (field, value) in fields(sandbox) {
+        match field {
+            1 => id = value.string()?,
+            2 => {
+                let metadata = value.bytes()?;
+                pod.name = string_field(metadata, 1);
+                pod.uid = string_field(metadata, 2);
+                pod.namespace = string_field(metadata, 3);
+            }
+            5 => {
+                // Map entries are messages with the key as 1 and the value as 2.
+                let entry = value.bytes()?;
+                pod.labels
+                    .insert(string_field(entry, 1), string_field(entry, 2));
+            }
+            _ => {}
+        }
+    }
+    if id.is_empty() {
+        return None;
+    }
+    Some((id, pod))
+}
+
+// A unary gRPC call with an empty request. Returns the response message.
+async fn call(socket: &Path, path: &str) -> Result<Vec<u8>, String> {
+    // A runtime that hangs would otherwise keep us from ever asking again.
+    tokio::time::timeout(CALL_TIMEOUT, send_call(socket, path))
+        .await
+        .map_err(|_| format!("{} timed out", path))?
+}
+
+async fn send_call(socket: &Path, path: &str) -> Result<Vec<u8>, String> {
+    let stream = UnixStream::connect(socket)
+        .await
+        .map_err(|err| format!("cannot connect to {}: {}", socket.display(), err))?;
+    let (client, connection) = h2::client::handshake(stream)
+        .await
+        .map_err(|err| err.to_string())?;
+    tokio::spawn(async move {
+        connection.await.ok();
+    });
+
+    let request = http::Request::builder()
+        .method("POST")
+        .uri(format!("http://localhost{}", path))
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(())
+        .map_err(|err| err.to_string())?;
+    let mut client = client.ready().await.map_err(|err| err.to_string())?;
+    let (response, mut send) = client
+        .send_request(request, false)
+        .map_err(|err| err.to_string())?;
+    // Not compressed, zero length.
+    send.send_data(Bytes::from_static(&[0, 0, 0, 0, 0]), true)
+        .map_err(|err| err.to_string())?;
+
+    let response = response.await.map_err(|err| err.to_string())?;
+    // A call that fails straight away has its status in the headers.
+    let mut status = grpc_status(response.headers());
+    let mut body = response.into_body();
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|err| err.to_string())?;
+        body.flow_control().release_capacity(chunk.len()).ok();
+        data.extend_from_slice(&chunk);
+    }
+    if let Some(trailers) = body.trailers().await.map_err(|err| err.to_string())? {
+        status = status.or_else(|| grpc_status(&trailers));
+    }
+    match status.as_deref() {
+        Some("0") => {}
+        Some(status) => return Err(format!("{} failed with gRPC status {}", path, status)),
+        None => return Err(format!("{} did not return a gRPC status", path)),
+    }
+
+    // A compressed flag and the length, then the message.
+    if data.len() < 5 || data[0] != 0 {
+        return Err(format!("unexpected response to {}", path));
+    }
+    let len = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+    data.get(5..5 + len)
+        .map(|message| message.to_vec())
+        .ok_or(format!("short response to {}", path))
+}
+
+fn grpc_status(headers: &http::HeaderMap) -> Option<String> {
+    headers
+        .get("grpc-status")
+        .and_then(|status| status.to_str().ok())
+        .map(String::from)
+}
+
+// Protobuf, as far as we need it.
+
+enum Value<'a> {
+    Bytes(&'a [u8]),
+    // Varints and fixed 32 and 64 bit values, which we never need.
+    Number,
+}
+
+impl<'a> Value<'a> {
+    fn bytes(&self) -> Option<&'a [u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    fn string(&self) -> Option<String> {
+        self.bytes()
+            .map(|bytes| String::from_utf8_lossy(bytes).to_string())
+    }
+}
+
+// The last occurrence of a string field, or an empty string, which is also what
+// protobuf says a missing string is.
+fn string_field(message: &[u8], number: u64) -> String {
+    fields(message)
+        .filter(|(field, _)| *field == number)
+        .filter_map(|(_, value)| value.string())
+        .last()
+        .unwrap_or_default()
+}
+
+// The fields of a message, by number. Stops at anything that doesn't parse.
+fn fields(message: &[u8]) -> impl Iterator<Item = (u64, Value<'_>)> {
+    let mut rest = message;
+    std::iter::from_fn(move || {
+        let (key, used) = varint(rest)?;
+        rest = &rest[used..];
+        let value = match key & 0x7 {
+            0 => {
+                let (_, used) = varint(rest)?;
+                rest = &rest[used..];
+                Value::Number
+            }
+            1 => {
+                rest = rest.get(8..)?;
+                Value::Number
+            }
+            2 => {
+                let (len, used) = varint(rest)?;
+                let end = used.checked_add(usize::try_from(len).ok()?)?;
+                let bytes = rest.get(used..end)?;
+                rest = &rest[end..];
+                Value::Bytes(bytes)
+            }
+            5 => {
+                rest = rest.get(4..)?;
+                Value::Number
+            }
+            _ => return None,
+        };
+        Some((key >> 3, value))
+    })
+}
+
+fn varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (idx, byte) in bytes.iter().take(10).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (7 * idx);
+        if byte & 0x80 == 0 {
+            return Some((value, idx + 1));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varints() {
+        assert_eq!(varint(&[0x00]), Some((0, 1)));
+        assert_eq!(varint(&[0x7f, 0xff]), Some((127, 1)));
+        assert_eq!(varint(&[0xac, 0x02]), Some((300, 2)));
+        assert_eq!(
+            varint(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]),
+            Some((u64::MAX, 10))
+        );
+        assert_eq!(varint(&[]), None);
+        assert_eq!(varint(&[0x80, 0x80]), None);
+        assert_eq!(varint(&[0x80; 11]), None);
+    }
+
+    #[test]
+    fn all_wire_types() {
+        let message = [
+            0x08, 0x96, 0x01, // 1: varint 150
+            0x11, 1, 2, 3, 4, 5, 6, 7, 8, // 2: 64 bit
+            0x1a, 0x02, b'h', b'i', // 3: "hi"
+            0x25, 1, 2, 3, 4, // 4: 32 bit
+        ];
+        let fields = fields(&message).collect::<Vec<(u64, Value)>>();
+        assert_eq!(
+            fields
+                .iter()
+                .map(|(number, _)| *number)
+                .collect::<Vec<u64>>(),
+            vec![1, 2, 3, 4]
+        );
+        assert_eq!(fields[2].1.string().as_deref(), Some("hi"));
+        assert!(fields[0].1.bytes().is_none());
+    }
+
+    #[test]
+    fn stops_at_garbage() {
+        // A string that runs past the end, and one that is longer than anything.
+        assert_eq!(fields(&[0x0a, 0x05, b'a']).count(), 0);
+        let huge = [
+            0x0a, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01,
+        ];
+        assert_eq!(fields(&huge).count(), 0);
+        // Groups are not supported.
+        assert_eq!(fields(&[0x08, 0x01, 0x0b]).count(), 1);
+    }
+
+    #[test]
+    fn last_string_wins() {
+        let message = [0x0a, 0x01, b'a', 0x12, 0x01, b'b', 0x0a, 0x01, b'c'];
+        assert_eq!(string_field(&message, 1), "c");
+        assert_eq!(string_field(&message, 2), "b");
+        assert_eq!(string_field(&message, 3), "");
+    }
+}
@@ -0,0 +1,102 @@
+// DNS lookups, as the probes on getaddrinfo() and gethostbyname() see them.
+//
+// Besides reporting on them, we remember per process which name an address came from.
+// Connections don't always tell us who they are for (no Host header, no SNI), but the
+// name a process looked up right before connecting is a good stand-in.
+use crate::endpoint;
+use probes::tls_mon::TlsEvent;
+use probes::tls_mon::BUFSIZE;
+use probes::tls_mon::DNS_ADDRS_OFFSET;
+use probes::tls_mon::DNS_ADDR_LEN;
+use probes::tls_mon::DNS_NAME_LEN;
+use probes::tls_mon::DNS_NAME_OFFSET;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::net::IpAddr;
+use std::path::Path;
+
+// Processes that connect to lots of places should not make us grow without bounds. If
+// one gets here, we start over for it.
+const MAX_NAMES_PER_PROCESS: usize = 4096;
+
+pub struct Lookup {
+    pub name: String,
+    pub status: i32,
+    pub start_ns: u64,
+    pub addresses: Vec<IpAddr>,
+}
+
+pub fn parse_lookup(event: &TlsEvent) -> Option<Lookup> {
+    let data = &event.data[0..event.len.min(BUFSIZE)];
+    let name = data.get(DNS_NAME_OFFSET..DNS_NAME_OFFSET + DNS_NAME_LEN)?;
+    let name = &name[0..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+    if name.is_empty() {
+        return None;
+    }
+    let mut addresses = Vec::new();
+    for slot in data[DNS_ADDRS_OFFSET..].chunks_exact(DNS_ADDR_LEN) {
+        if let Some(addr) = endpoint::parse_sockaddr(slot) {
+            if !addresses.contains(&addr.ip()) {
+                addresses.push(addr.ip());
+            }
+        }
+    }
+    Some(Lookup {
+        name: String::from_utf8_lossy(name).to_string(),
+        status: event.handle as i32,
+        start_ns: u64::from_ne_bytes(data[0..8].try_into().ok()?),
+        addresses,
+    })
+}
+
+// The EAI_* codes from glibc's netdb.h.
+pub fn status_name(status: i32) -> String {
+    match status {
+        0 => String::from("ok"),
+        -2 => String::from("EAI_NONAME"),
+        -3 => String::from("EAI_AGAIN"),
+        -4 => String::from("EAI_FAIL"),
+        -5 => String::from("EAI_NODATA"),
+        -6 => String::from("EAI_FAMILY"),
+        -8 => String::from("EAI_SERVICE"),
+        -10 => String::from("EAI_MEMORY"),
+        -11 => String::from("EAI_SYSTEM"),
+        other => format!("EAI({})", other),
+    }
+}
+
+// Names that processes looked up, by process and address.
+pub struct Names {
+    by_process: HashMap<u32, HashMap<IpAddr, String>>,
+}
+
+impl Names {
+    pub fn new() -> Names {
+        Names {
+            by_process: HashMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, tgid: u32, lookup: &Lookup) {
+        let names = self.by_process.entry(tgid).or_insert_with(HashMap::new);
+        if names.len() + lookup.addresses.len() > MAX_NAMES_PER_PROCESS {
+            names.clear();
+        }
+        for address in &lookup.addresses {
+            names.insert(*address, lookup.name.clone());
+        }
+    }
+
+    pub fn name_for(&self, tgid: u32, address: &IpAddr) -> Option<&str> {
+        self.by_process
+            .get(&tgid)?
+            .get(address)
+            .map(|name| name.as_str())
+    }
+
+    // Forget about processes that are gone.
+    pub fn cleanup(&mut self) {
+        self.by_process
+            .retain(|tgid, _| Path::new(&format!("/proc/{}", tgid)).is_dir());
+    }
+}
@@ -0,0 +1,153 @@
+// Where connections go.
+//
+// The kernel probe on connect() gives us the address a process connects a socket to,
+// and the TLS probes tell us which socket a connection runs over. For sockets that got
+// connected before we were looking, we ask the kernel through /proc instead.
+use std::fs;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
+
+// A struct sockaddr_in or sockaddr_in6, as the process passed it to connect().
+pub fn parse_sockaddr(bytes: &[u8]) -> Option<SocketAddr> {
+    if bytes.len() < 16 {
+        return None;
+    }
+    let family = u16::from_ne_bytes([bytes[0], bytes[1]]);
+    let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+    let ip: IpAddr = match family {
+        2 => Ipv4Addr::new(bytes[4], bytes[5], bytes[6], bytes[7]).into(),
+        10 => {
+            let mut addr = [0u8; 16];
+            addr.copy_from_slice(bytes.get(8..24)?);
+            unmap(Ipv6Addr::from(addr))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+// The remote end of a TCP socket of a process, looked up through the socket's inode in
+// the connection tables of its network namespace.
+pub fn socket_peer(pid: u32, fd: u32) -> Option<SocketAddr> {
+    let link = fs::read_link(format!("/proc/{}/fd/{}", pid, fd)).ok()?;
+    let inode = link
+        .to_str()?
+        .strip_prefix("socket:[")?
+        .strip_suffix(']')?
+        .to_string();
+    ["tcp", "tcp6"].iter().find_map(|table| {
+        let connections = fs::read_to_string(format!("/proc/{}/net/{}", pid, table)).ok()?;
+        connections.lines().skip(1).find_map(|line| {
+            // sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode
+            let fields = line.split_ascii_whitespace().collect::<Vec<&str>>();
+            if fields.get(9) != Some(&inode.as_str()) {
+                return None;
+            }
+            parse_proc_addr(fields.get(2)?)
+        })
+    })
+}
+
+// "0100007F:1F90", or with 32 hex digits for IPv6. The address is printed as 32 bit
+// words in host byte order, the port as a number.
+fn parse_proc_addr(field: &str) -> Option<SocketAddr> {
+    let (addr, port) = field.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let mut bytes = Vec::with_capacity(16);
+    for idx in (0..addr.len()).step_by(8) {
+        let word = u32::from_str_radix(addr.get(idx..idx + 8)?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    let ip: IpAddr = match bytes.len() {
+        4 => Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]).into(),
+        16 => {
+            let mut addr = [0u8; 16];
+            addr.copy_from_slice(&bytes);
+            unmap(Ipv6Addr::from(addr))
+        }
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+// IPv4 connections on IPv6 sockets have IPv4 mapped addresses, "::ffff:10.0.0.1".
+fn unmap(addr: Ipv6Addr) -> IpAddr {
+    match addr.segments() {
+        [0, 0, 0, 0, 0, 0xffff, ..] => addr.to_ipv4().map_or(addr.into(), IpAddr::from),
+        _ => addr.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sockaddr_in() {
+        let mut bytes = [0u8; 16];
+        bytes[0..2].copy_from_slice(&2u16.to_ne_bytes());
+        bytes[2..4].copy_from_slice(&443u16.to_be_bytes());
+        bytes[4..8].copy_from_slice(&[10, 0, 0, 1]);
+        assert_eq!(
+            parse_sockaddr(&bytes),
+            Some("10.0.0.1:443".parse().unwrap())
+        );
+        assert_eq!(parse_sockaddr(&bytes[..15]), None);
+    }
+
+    #[test]
+    fn sockaddr_in6() {
+        let mut bytes = [0u8; 28];
+        bytes[0..2].copy_from_slice(&10u16.to_ne_bytes());
+        bytes[2..4].copy_from_slice(&8443u16.to_be_bytes());
+        let addr: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        bytes[8..24].copy_from_slice(&addr.octets());
+        assert_eq!(
+            parse_sockaddr(&bytes),
+            Some("[2001:db8::1]:8443".parse().unwrap())
+        );
+
+        let mapped: Ipv6Addr = "::ffff:192.168.1.2".parse().unwrap();
+        bytes[8..24].copy_from_slice(&mapped.octets());
+        assert_eq!(
+            parse_sockaddr(&bytes),
+            Some("192.168.1.2:8443".parse().unwrap())
+        );
+        assert_eq!(parse_sockaddr(&bytes[..20]), None);
+    }
+
+    #[test]
+    fn other_families() {
+        let mut bytes = [0u8; 16];
+        bytes[0..2].copy_from_slice(&1u16.to_ne_bytes());
+        assert_eq!(parse_sockaddr(&bytes), None);
+    }
+
+    // As the kernel prints them, on a little endian machine.
+    #[cfg(target_endian = "little")]
+    #[test]
+    fn proc_addrs() {
+        assert_eq!(
+            parse_proc_addr("0100007F:1F90"),
+            Some("127.0.0.1:8080".parse().unwrap())
+        );
+        assert_eq!(
+            parse_proc_addr("B80D0120000000000000000001000000:01BB"),
+            Some("[2001:db8::1]:443".parse().unwrap())
+        );
+        assert_eq!(
+            parse_proc_addr("0000000000000000FFFF00000201A8C0:01BB"),
+            Some("192.168.1.2:443".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn bad_proc_addrs() {
+        assert_eq!(parse_proc_addr("0100007F"), None);
+        assert_eq!(parse_proc_addr("0100007F:XYZ"), None);
+        assert_eq!(parse_proc_addr("0100:1F90"), None);
+        assert_eq!(parse_proc_addr("0100007F00:1F90"), None);
+    }
+}
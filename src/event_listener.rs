@@ -1,55 +1,181 @@
 /// This is where the event listening work happens. We run a
 /// thread that reads the event stream and processes it.
+use crate::container::Container;
+use crate::container::Containers;
+use crate::dns;
+use crate::endpoint;
+use crate::exporter::Exporter;
+use crate::grpc;
+use crate::http1;
+use crate::http2;
 use crate::open_listener::OpenMsg;
-use futures::channel::mpsc::UnboundedReceiver;
+use crate::process::Process;
+use crate::process::Processes;
+use crate::record;
+use crate::record::DnsRecord;
+use crate::record::Record;
 use futures::stream::Stream;
 use futures::stream::StreamExt;
 use probes::tls_mon::Kind;
+use probes::tls_mon::Library;
 use probes::tls_mon::TlsEvent;
+use probes::tls_mon::BUFSIZE;
+use probes::tls_mon::CONNECT_LEN;
+use probes::tls_mon::CONNECT_SOCK_OFFSET;
 use redbpf::load::map_io::PerfMessageStream;
 use std::collections::HashMap;
-use std::net::UdpSocket;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::net::SocketAddr;
 use std::path::Path;
 use std::ptr;
+use std::sync::Arc;
 use std::time::Instant;
 use sysinfo::PidExt;
 use tokio::sync::mpsc::Sender;
 use tokio::task::JoinHandle;
 
+// If we never see responses coming in, we don't want to queue up
+// requests forever.
+const MAX_PENDING_REQUESTS: usize = 64;
+
+// We never hear about rustls connections going away, so we drop them when they
+// have been idle for this long.
+const RUSTLS_IDLE_SECS: u64 = 15 * 60;
+
+// A rustls connection that has been idle for this long and then writes a request is
+// taken to be a new connection at the address of an old one.
+const RUSTLS_REUSE_IDLE_SECS: u64 = 10;
+
+// Connections get used right after they are up. If we saw one come up this long ago
+// and it did not get used yet, it is one that we don't look at.
+const CONNECTED_MAX_AGE_NS: u64 = 60 * 1_000_000_000;
+
+// A rustls reader's handle points into its connection; this is how far in we look.
+const MAX_RUSTLS_READER_OFFSET: u64 = 64 * 1024;
+
 // Here we keep some data about state of an SSL handle around
 // so we know where we are.
 struct Handle {
     is_h2: bool,
     pid: u32,
-    // For HTTP/1.1, we keep state here.
+    tgid: u32,
+    process: Option<Arc<Process>>,
+    container: Option<Arc<Container>>,
+    library: Library,
+    tls_library: &'static str,
+    last_active: Instant,
+    // Where the connection goes, as far as we know. `fd` is the socket it runs over.
+    fd: Option<u32>,
+    remote: Option<SocketAddr>,
+    looked_up_remote: bool,
+    sni: String,
+    // The name that the process looked up to get to the remote address.
+    resolved_name: String,
+    // How long it took to set the connection up, as far as we saw it. Only the first
+    // transaction on a connection reports this.
+    connect_start_ns: u64,
+    connected_ns: u64,
+    handshake_start_ns: u64,
+    handshake_end_ns: u64,
+    reported_setup: bool,
+    // For HTTP/1.1, we keep state here. Requests are queued in the order
+    // they were written, which is the order the responses will come in.
+    requests: VecDeque<PendingRequest>,
+    // For HTTP/2, we keep state here.
+    streams: HashMap<u32, Transaction>,
+    request_headers: http2::HeaderDecoder,
+    response_headers: http2::HeaderDecoder,
+    write_frames: http2::FrameBuffer,
+    read_frames: http2::FrameBuffer,
+}
+
+// A single request/response exchange, which is what we report on.
+struct Transaction {
+    protocol: &'static str,
+    tls_library: &'static str,
+    tgid: u32,
+    process: Option<Arc<Process>>,
+    container: Option<Arc<Container>>,
+    remote: Option<SocketAddr>,
+    sni: String,
+    resolved_name: String,
+    connect_start_ns: u64,
+    connected_ns: u64,
+    handshake_start_ns: u64,
+    handshake_end_ns: u64,
     start_ns: u64,
+    // When the first of the response came in.
+    first_byte_ns: u64,
     last_ns: u64,
     method: String,
     url: String,
     host: String,
-    // For HTTP/2, we keep state here.
-    streams: HashMap<u32, Handle>,
-    decoder: h2::hpack::Decoder,
+    status: u16,
+    content_type: String,
+    // For gRPC calls, the HTTP status is always 200 and these tell the real story.
+    grpc_service: String,
+    grpc_method: String,
+    grpc_status: String,
+    grpc_message: String,
+    // Why we could not make sense of (part of) the headers.
+    decode_error: String,
+    request_bytes: usize,
+    response_bytes: usize,
+}
+
+// A socket that a process connected.
+struct Connection {
+    remote: SocketAddr,
+    // The struct sock pointer, and when the connect started.
+    sock: u64,
+    start_ns: u64,
+}
+
+// An HTTP/1.1 request that is waiting for (the rest of) its response.
+struct PendingRequest {
+    transaction: Transaction,
+    response: http1::Response,
 }
 
 #[allow(unused_must_use)]
-pub fn start_event_listener(
-    event_stream: UnboundedReceiver<(String, <PerfMessageStream as Stream>::Item)>,
-    sock: UdpSocket,
+pub fn start_event_listener<S>(
+    event_stream: S,
+    exporter: Arc<Exporter>,
     tx: Sender<OpenMsg>,
-) -> JoinHandle<()> {
+    containers: Containers,
+    processes: Processes,
+) -> JoinHandle<()>
+where
+    S: Stream<Item = (String, <PerfMessageStream as Stream>::Item)> + Unpin + Send + 'static,
+{
     tokio::spawn(async move {
-        run_event_listener(event_stream, sock, tx).await;
+        run_event_listener(event_stream, exporter, tx, containers, processes).await;
     })
 }
 
 #[allow(unused_must_use)]
-async fn run_event_listener(
-    mut event_stream: UnboundedReceiver<(String, <PerfMessageStream as Stream>::Item)>,
-    sock: UdpSocket,
+async fn run_event_listener<S>(
+    mut event_stream: S,
+    exporter: Arc<Exporter>,
     tx: Sender<OpenMsg>,
-) {
-    let mut handles = HashMap::new();
+    containers: Containers,
+    mut processes: Processes,
+) where
+    S: Stream<Item = (String, <PerfMessageStream as Stream>::Item)> + Unpin,
+{
+    // Connections, by tgid and handle. Handles are pointers, and processes don't all
+    // have their own: Go's heap starts at the same address in every process.
+    let mut handles = HashMap::<(u32, u64), Handle>::new();
+    // rustls reader handles, mapped onto the connection they belong to.
+    let mut rustls_readers = HashMap::<(u32, u64), (u32, u64)>::new();
+    // Where sockets got connected to, by tgid and file descriptor, and when connections
+    // were up, by socket. File descriptors get reused, so we forget about a connection
+    // when its socket closes, and need to find it by socket for that.
+    let mut connections = HashMap::<(u32, u32), Connection>::new();
+    let mut socket_fds = HashMap::<u64, (u32, u32)>::new();
+    let mut connected = HashMap::<u64, u64>::new();
+    let mut names = dns::Names::new();
     println!("Listening for eBPF events ...");
     let mut last_cleanup = Instant::now();
     while let Some((_name, events)) = event_stream.next().await {
@@ -57,74 +183,164 @@ async fn run_event_listener(
             if last_cleanup.elapsed().as_secs() > 60 {
                 let pre_len = handles.len();
                 handles.retain(|_, h: &mut Handle| Path::new(format!("/proc/{}", h.pid).as_str()).is_dir());
+                handles.retain(|_, h| {
+                    !matches!(h.library, Library::Rustls)
+                        || h.last_active.elapsed().as_secs() < RUSTLS_IDLE_SECS
+                });
+                rustls_readers.retain(|_, conn| handles.contains_key(conn));
+                connections.retain(|(tgid, _), _| Path::new(&format!("/proc/{}", tgid)).is_dir());
+                socket_fds.retain(|_, key| connections.contains_key(key));
+                let now_ns = record::ktime_now_ns();
+                connected.retain(|_, ns| now_ns.saturating_sub(*ns) < CONNECTED_MAX_AGE_NS);
+                names.cleanup();
+                processes.cleanup();
                 let post_len = handles.len();
-                println!("Cleanup: Cleaned {} handles, remaining {}, capacity {}",
-                         pre_len - post_len,
-                         post_len,
-                         handles.capacity());
+                println!(
+                    "Cleanup: Cleaned {} handles, remaining {}, capacity {}",
+                    pre_len - post_len,
+                    post_len,
+                    handles.capacity()
+                );
+                exporter.log_stats();
 
                 last_cleanup = Instant::now();
             }
             let tls_event = unsafe { ptr::read(event.as_ptr() as *const TlsEvent) };
+            let key = (tls_event.tgid, tls_event.handle);
             let mut do_print = false;
             match tls_event.kind {
                 Kind::New => {
-                    let hdl = Handle {
-                        pid: tls_event.pid,
-                        ..Default::default()
-                    };
-                    handles.insert(tls_event.handle, hdl);
+                    handles.insert(key, Handle::new(&tls_event, &containers, &mut processes));
                 }
-                Kind::Write => {
-                    if let Some(handle) = handles.get_mut(&tls_event.handle) {
-                        maybe_update_protocol_data(handle, &tls_event);
+                Kind::Write | Kind::PlainWrite | Kind::SendFile => {
+                    // There is no single place where Go or rustls create their TLS
+                    // connections that we can hook, so there a connection starts with
+                    // its first write. The same goes for plaintext sockets.
+                    if matches!(tls_event.library, Library::Rustls) {
+                        let is_new = handles
+                            .get(&key)
+                            .map_or(false, |handle| is_new_rustls_connection(handle, &tls_event));
+                        if is_new {
+                            if let Some(old) = handles.remove(&key) {
+                                for pending in old.requests {
+                                    finish_request(&exporter, pending, tls_event.ts);
+                                }
+                            }
+                            rustls_readers.retain(|_, conn| *conn != key);
+                        }
+                    }
+                    if matches!(
+                        tls_event.library,
+                        Library::Go | Library::Rustls | Library::Plain | Library::Ktls
+                    ) {
+                        handles.entry(key).or_insert_with(|| {
+                            Handle::new(&tls_event, &containers, &mut processes)
+                        });
+                    }
+                    if let Some(handle) = handles.get_mut(&key) {
+                        handle.last_active = Instant::now();
+                        if handle.remote.is_none() {
+                            resolve_connection(handle, &connections, &connected, &names);
+                        }
+                        maybe_update_protocol_data(&exporter, handle, &tls_event);
                     }
                 }
-                Kind::Read => {
-                    // on every read, we update the timestamp. Then, the next write
-                    // or the free indicates that that was the last read, and we use
-                    // that timestamp to emit a message. This way, we don't need to
-                    // parse the protocol.
-                    if let Some(handle) = handles.get_mut(&tls_event.handle) {
+                Kind::Read | Kind::PlainRead => {
+                    // For HTTP/2, we look for the end of the stream. For HTTP/1.1, we
+                    // parse the responses and emit a message as soon as one is complete.
+                    let key = match tls_event.library {
+                        Library::Rustls => {
+                            rustls_connection(&handles, &mut rustls_readers, &tls_event)
+                        }
+                        _ => key,
+                    };
+                    if let Some(handle) = handles.get_mut(&key) {
+                        handle.last_active = Instant::now();
                         if handle.is_h2 {
-                            let head = h2::frame::Head::parse(&tls_event.data);
-                            let stream_id = head.stream_id().value();
-                            if stream_id > 0 {
-                                if let Some(stream_handle) = handle.streams.get_mut(&stream_id) {
-                                    stream_handle.last_ns = tls_event.ts;
-                                    if (head.kind() == h2::frame::Kind::Headers
-                                        || head.kind() == h2::frame::Kind::Data)
-                                        && head.flag() & 0x01 == 0x01
-                                    {
-                                        let delta_ns =
-                                            stream_handle.last_ns - stream_handle.start_ns;
-                                        send_stats_line(&sock, stream_handle, delta_ns);
-                                    }
-                                }
-                            }
+                            process_http2_read(&exporter, handle, &tls_event);
                         } else {
-                            handle.last_ns = tls_event.ts;
+                            process_http1_read(&exporter, handle, &tls_event);
+                        }
+                    }
+                }
+                Kind::Connect => {
+                    let data = captured(&tls_event);
+                    if let (Some(remote), Some(sock)) = (
+                        endpoint::parse_sockaddr(data),
+                        data.get(CONNECT_SOCK_OFFSET..CONNECT_LEN),
+                    ) {
+                        let connection = Connection {
+                            remote,
+                            sock: u64::from_ne_bytes(sock[0..8].try_into().unwrap()),
+                            start_ns: u64::from_ne_bytes(sock[8..16].try_into().unwrap()),
+                        };
+                        // Without the socket, we would not hear about it closing.
+                        if connection.sock != 0 {
+                            let fd_key = (tls_event.tgid, tls_event.handle as u32);
+                            socket_fds.insert(connection.sock, fd_key);
+                            connections.insert(fd_key, connection);
+                        }
+                    }
+                }
+                Kind::Closed => {
+                    if let Some(fd_key) = socket_fds.remove(&tls_event.handle) {
+                        // Unless the file descriptor got connected again already.
+                        if connections
+                            .get(&fd_key)
+                            .map_or(false, |connection| connection.sock == tls_event.handle)
+                        {
+                            connections.remove(&fd_key);
+                        }
+                    }
+                    connected.remove(&tls_event.handle);
+                }
+                Kind::Connected => {
+                    connected.insert(tls_event.handle, tls_event.ts);
+                }
+                Kind::SetFd => {
+                    if let Some(handle) = handles.get_mut(&key) {
+                        // The socket may not be connected yet, so we find out where it
+                        // goes when the first request is written.
+                        handle.fd = captured(&tls_event).try_into().ok().map(u32::from_ne_bytes);
+                        handle.remote = None;
+                        handle.looked_up_remote = false;
+                    }
+                }
+                Kind::Sni => {
+                    if let Some(handle) = handles.get_mut(&key) {
+                        let name = captured(&tls_event);
+                        let name = name.strip_suffix(&[0]).unwrap_or(name);
+                        handle.sni = String::from_utf8_lossy(name).to_string();
+                    }
+                }
+                Kind::Handshake => {
+                    if let Some(handle) = handles.get_mut(&key) {
+                        if let Ok(start) = captured(&tls_event).try_into() {
+                            handle.handshake_start_ns = u64::from_ne_bytes(start);
+                            handle.handshake_end_ns = tls_event.ts;
                         }
                     }
                 }
+                Kind::Dns => {
+                    if let Some(lookup) = dns::parse_lookup(&tls_event) {
+                        send_dns_line(
+                            &exporter,
+                            &tls_event,
+                            &lookup,
+                            processes.get(tls_event.tgid),
+                        );
+                        names.add(tls_event.tgid, &lookup);
+                    }
+                }
                 Kind::Free => {
-                    if let Some(handle) = handles.get(&tls_event.handle) {
-                        if !handle.is_h2 {
-                            if handle.start_ns > 0 {
-                                // If we had a last read, we use that as the timestamp because it is likely to
-                                // be more precise measurement of the transaction than waiting for whenever
-                                // the caller gets around freeing this.
-                                let last_ns = if handle.last_ns > 0 {
-                                    handle.last_ns
-                                } else {
-                                    tls_event.ts
-                                };
-                                let delta_ns = last_ns - handle.start_ns;
-                                send_stats_line(&sock, handle, delta_ns);
-                            }
+                    if let Some(handle) = handles.remove(&key) {
+                        // Whatever is still pending ends here. If we had a last read, we use
+                        // that as the timestamp because it is likely to be more precise
+                        // measurement of the transaction than waiting for whenever the caller
+                        // gets around freeing this.
+                        for pending in handle.requests {
+                            finish_request(&exporter, pending, tls_event.ts);
                         }
-
-                        handles.remove(&tls_event.handle);
                     }
                 }
                 Kind::OpenAt => {
@@ -142,11 +358,23 @@ async fn run_event_listener(
                             let msg = OpenMsg {
                                 lib_name: buf.to_string(),
                                 pid: tls_event.pid,
+                                tgid: tls_event.tgid,
+                                is_exec: false,
                             };
                             tx.send(msg).await;
                         }
                     }
                 }
+                Kind::Exec => {
+                    // The open listener checks whether this is something we want to probe.
+                    let msg = OpenMsg {
+                        lib_name: String::from(""),
+                        pid: tls_event.tgid,
+                        tgid: tls_event.tgid,
+                        is_exec: true,
+                    };
+                    tx.send(msg).await;
+                }
 
                 Kind::Unset => {
                     println!("Unexpected packet with [Unset] kind!");
@@ -173,78 +401,464 @@ async fn run_event_listener(
     }
 }
 
-fn maybe_update_protocol_data(handle: &mut Handle, event: &TlsEvent) {
+fn maybe_update_protocol_data(exporter: &Exporter, handle: &mut Handle, event: &TlsEvent) {
+    let mut data = captured(event);
+    let mut len = event.len;
     if is_h2_hdr(event) {
         handle.is_h2 = true;
-        return;
+        // The first frames may be written together with the preface.
+        data = &data[H2_HDR_LEN..];
+        len -= H2_HDR_LEN;
     }
 
     if handle.is_h2 {
-        let head = h2::frame::Head::parse(&event.data);
-        if head.kind() == h2::frame::Kind::Headers {
-            let cdata = &event.data[h2::frame::HEADER_LEN..event.len];
-            let bm = bytes::BytesMut::from(cdata);
-            let (mut headers, mut rest) =
-                h2::frame::Headers::load(head, bm).expect("Cannot parse headers");
-            let stream_id = headers.stream_id().value();
-            let result = headers.load_hpack(&mut rest, 16 << 20, &mut handle.decoder);
+        for frame in handle.write_frames.feed(data, len) {
+            let headers = match handle.request_headers.process(frame) {
+                Ok(Some(headers)) => headers,
+                Ok(None) => continue,
+                Err(err) => {
+                    let failed = handle.request_headers.take_failed_block();
+                    let decode_error = err.to_string();
+                    header_error(handle, err);
+                    // Still a request, we just don't know what for.
+                    if let Some((stream_id, _)) = failed {
+                        let transaction = Transaction {
+                            decode_error,
+                            ..handle.new_transaction("HTTP/2", event.ts)
+                        };
+                        handle.streams.insert(stream_id, transaction);
+                    }
+                    continue;
+                }
+            };
             println!("Headers: {:?}", headers);
-            println!(" parse result: {:?}", result);
-            let (pseudo, _fields) = headers.into_parts();
+            let stream_id = headers.stream_id().value();
+            let (pseudo, fields) = headers.into_parts();
 
-            let mut stream_handle = Handle {
-                ..Default::default()
+            let mut transaction = Transaction {
+                method: String::from(pseudo.method.unwrap_or_default().as_str()),
+                host: String::from_utf8_lossy(pseudo.authority.unwrap_or_default().as_ref())
+                    .to_string(),
+                url: String::from_utf8_lossy(pseudo.path.unwrap_or_default().as_ref()).to_string(),
+                ..handle.new_transaction("HTTP/2", event.ts)
             };
-            stream_handle.method = String::from(pseudo.method.unwrap_or_default().as_str());
-            stream_handle.host =
-                String::from_utf8_lossy(pseudo.authority.unwrap_or_default().as_ref()).to_string();
-            stream_handle.url =
-                String::from_utf8_lossy(pseudo.path.unwrap_or_default().as_ref()).to_string();
-            // Reset timings on a new stream
-            stream_handle.last_ns = 0;
-            stream_handle.start_ns = event.ts;
-
-            handle.streams.insert(stream_id, stream_handle);
+            if let Some(value) = fields.get("content-type") {
+                if grpc::is_grpc(&String::from_utf8_lossy(value.as_bytes())) {
+                    set_grpc_call(&mut transaction);
+                }
+            }
+
+            handle.streams.insert(stream_id, transaction);
         }
-    } else {
-        let cdata = &event.data[0..event.len];
-        let buf = String::from_utf8_lossy(&cdata);
-        for line in buf.lines() {
-            let lower = line.to_ascii_lowercase();
-            let elems: Vec<&str> = line.split_ascii_whitespace().collect();
-
-            if lower.starts_with("host: ") {
-                handle.host = (&line[6..]).to_string();
+    } else if let Some(request) = http1::parse_request(captured(event)) {
+        // A new request means that a client is done with any response that we could not
+        // find the end of. Those that have not started yet are still to come (pipelining).
+        while let Some(pending) = handle.requests.front() {
+            if pending.response.is_started() && !pending.response.is_delimited() {
+                let pending = handle.requests.pop_front().unwrap();
+                finish_request(exporter, pending, event.ts);
+            } else {
+                break;
+            }
+        }
+        if handle.requests.len() >= MAX_PENDING_REQUESTS {
+            println!("Too many pending requests on handle, dropping oldest");
+            handle.requests.pop_front();
+        }
+        let transaction = Transaction {
+            method: request.method,
+            url: request.url,
+            host: request.host,
+            request_bytes: event.len,
+            ..handle.new_transaction("HTTP/1.1", event.ts)
+        };
+        let response = http1::Response::new(transaction.method == "HEAD");
+        handle.requests.push_back(PendingRequest {
+            transaction,
+            response,
+        });
+    } else if let Some(pending) = handle.requests.back_mut() {
+        // Not the start of a request, so more of the last one (typically the body).
+        pending.transaction.request_bytes += event.len;
+    }
+}
+
+// Go through the frames the server sent us. All header blocks need to be decoded, even
+// for streams we don't know about, otherwise the HPACK dynamic table gets out of sync.
+fn process_http2_read(exporter: &Exporter, handle: &mut Handle, event: &TlsEvent) {
+    for frame in handle.read_frames.feed(captured(event), event.len) {
+        let head = frame.head;
+        let mut stream_id = head.stream_id().value();
+        // The end of a stream is either flagged on a DATA frame, or on a header block
+        // (which can only be looked at once it is complete).
+        let mut is_end_stream = head.kind() == h2::frame::Kind::Data && head.flag() & 0x01 == 0x01;
+        let mut error = None;
+        match handle.response_headers.process(frame) {
+            Ok(Some(headers)) => {
+                stream_id = headers.stream_id().value();
+                is_end_stream = headers.is_end_stream();
+                if let Some(transaction) = handle.streams.get_mut(&stream_id) {
+                    let (pseudo, fields) = headers.into_parts();
+                    if let Some(status) = pseudo.status {
+                        transaction.status = status.as_u16();
+                    }
+                    if let Some(value) = fields.get("content-type") {
+                        transaction.content_type =
+                            String::from_utf8_lossy(value.as_bytes()).to_string();
+                        if grpc::is_grpc(&transaction.content_type) {
+                            set_grpc_call(transaction);
+                        }
+                    }
+                    // These normally come in the trailers, but a call that fails
+                    // straight away has them in the only header block.
+                    if let Some(value) = fields.get("grpc-status") {
+                        transaction.grpc_status =
+                            String::from_utf8_lossy(value.as_bytes()).to_string();
+                    }
+                    if let Some(value) = fields.get("grpc-message") {
+                        transaction.grpc_message = grpc::decode_message(value.as_bytes());
+                    }
+                }
+            }
+            Ok(None) => {}
+            Err(err) => {
+                // We lose what was in the block, but not that it may end the stream.
+                if let Some((id, end_stream)) = handle.response_headers.take_failed_block() {
+                    stream_id = id;
+                    is_end_stream = end_stream;
+                    if let Some(transaction) = handle.streams.get_mut(&stream_id) {
+                        transaction.decode_error = err.to_string();
+                    }
+                }
+                error = Some(err);
             }
-            if elems.len() == 3 && is_method(elems[0]) {
-                handle.method = String::from(elems[0]);
-                handle.url = String::from(elems[1]);
+        }
+        if let Some(transaction) = handle.streams.get_mut(&stream_id) {
+            if transaction.first_byte_ns == 0 {
+                transaction.first_byte_ns = event.ts;
             }
+            transaction.last_ns = event.ts;
+            if is_end_stream {
+                send_stats_line(exporter, transaction);
+                handle.streams.remove(&stream_id);
+            }
+        }
+        if let Some(err) = error {
+            header_error(handle, err);
         }
-        // Reset timings on write.
-        handle.last_ns = 0;
-        handle.start_ns = event.ts;
     }
 }
 
-#[allow(unused_must_use)]
-fn send_stats_line(sock: &UdpSocket, handle: &Handle, delta_ns: u64) {
-    let delta_ms = delta_ns as f32 / (1000.0 * 1000.0);
-    let msg = format!(
-        "0\t{}\t{}\t{}\t{}\n",
-        handle.method, handle.host, handle.url, delta_ms
-    );
-    sock.send(msg.as_bytes());
-    println!("++ seen {}", msg);
+// The path of a gRPC call tells us the service and the method.
+fn set_grpc_call(transaction: &mut Transaction) {
+    if transaction.grpc_service.is_empty() {
+        if let Some((service, method)) = grpc::split_path(&transaction.url) {
+            transaction.grpc_service = service;
+            transaction.grpc_method = method;
+        }
+    }
+}
+
+// A header block we cannot decode is either just a bad request or response, or it
+// means that we lost track of the dynamic table. In the latter case, nothing that
+// is going on on this connection can be trusted anymore so we start over.
+fn header_error(handle: &mut Handle, err: http2::HeaderError) {
+    if err.is_fatal() {
+        println!("Cannot decode headers ({}), resetting HTTP/2 state", err);
+        handle.streams.clear();
+        handle.request_headers = http2::HeaderDecoder::new();
+        handle.response_headers = http2::HeaderDecoder::new();
+    } else {
+        println!("Skipping headers: {}", err);
+    }
+}
+
+// Hand a read to the responses we are waiting for, in order. A single read can
+// complete one response and carry the start of the next one.
+fn process_http1_read(exporter: &Exporter, handle: &mut Handle, event: &TlsEvent) {
+    let mut data = captured(event);
+    let mut len = event.len;
+    while len > 0 {
+        let queued = handle.requests.len();
+        let pending = match handle.requests.front_mut() {
+            Some(pending) => pending,
+            None => break,
+        };
+        // If we lost track of a response, the only way to get back in sync for
+        // pipelined requests is the next status line.
+        if pending.response.is_started()
+            && !pending.response.is_delimited()
+            && queued > 1
+            && data.starts_with(b"HTTP/1.")
+        {
+            let pending = handle.requests.pop_front().unwrap();
+            finish_request(exporter, pending, event.ts);
+            continue;
+        }
+        let consumed = pending.response.feed(data, len);
+        if pending.transaction.first_byte_ns == 0 {
+            pending.transaction.first_byte_ns = event.ts;
+        }
+        pending.transaction.last_ns = event.ts;
+        data = &data[consumed.min(data.len())..];
+        len -= consumed;
+        if pending.response.is_complete() {
+            let pending = handle.requests.pop_front().unwrap();
+            finish_request(exporter, pending, event.ts);
+        }
+    }
+}
+
+// Emit a request. `ts` is used as the end time if no response was seen at all.
+fn finish_request(exporter: &Exporter, pending: PendingRequest, ts: u64) {
+    let mut transaction = pending.transaction;
+    if transaction.last_ns == 0 {
+        transaction.last_ns = ts;
+    }
+    transaction.status = pending.response.status;
+    transaction.content_type = pending.response.content_type;
+    transaction.response_bytes = pending.response.bytes;
+    send_stats_line(exporter, &transaction);
+}
+
+// Where a connection goes and how long connecting took, from the connect() of its
+// socket. Without that, we ask the kernel where it goes, but only once.
+fn resolve_connection(
+    handle: &mut Handle,
+    connections: &HashMap<(u32, u32), Connection>,
+    connected: &HashMap<u64, u64>,
+    names: &dns::Names,
+) {
+    let fd = match handle.fd {
+        Some(fd) => fd,
+        None => return,
+    };
+    if let Some(connection) = connections.get(&(handle.tgid, fd)) {
+        handle.remote = Some(connection.remote);
+        if let Some(connected_ns) = connected.get(&connection.sock) {
+            handle.connect_start_ns = connection.start_ns;
+            handle.connected_ns = *connected_ns;
+        }
+    } else if !handle.looked_up_remote {
+        handle.looked_up_remote = true;
+        handle.remote = endpoint::socket_peer(handle.tgid, fd);
+    }
+    if let Some(remote) = handle.remote {
+        if let Some(name) = names.name_for(handle.tgid, &remote.ip()) {
+            handle.resolved_name = name.to_string();
+        }
+    }
+}
+
+// The probes report the full length of a read or write but only
+// capture what fits in the event.
+fn captured(event: &TlsEvent) -> &[u8] {
+    match event.kind {
+        // Sent straight from a file, we only know how much.
+        Kind::SendFile => &[],
+        _ => &event.data[0..event.len.min(BUFSIZE)],
+    }
+}
+
+fn send_stats_line(exporter: &Exporter, transaction: &Transaction) {
+    let pod = transaction
+        .container
+        .as_ref()
+        .and_then(|container| container.pod());
+    // Perf buffers are per CPU, so events can come in out of order.
+    let delta_ns = transaction.last_ns.saturating_sub(transaction.start_ns);
+    let connect_ns = span_ns(transaction.connect_start_ns, transaction.connected_ns);
+    let handshake_ns = span_ns(transaction.handshake_start_ns, transaction.handshake_end_ns);
+    let ttfb_ns = span_ns(transaction.start_ns, transaction.first_byte_ns);
+    // From the start of whatever we saw of setting up the connection.
+    let first_ns = [
+        transaction.connect_start_ns,
+        transaction.handshake_start_ns,
+        transaction.start_ns,
+    ]
+    .iter()
+    .copied()
+    .find(|ns| *ns != 0)
+    .unwrap_or(transaction.start_ns);
+    let grpc = if transaction.grpc_service.is_empty() {
+        None
+    } else {
+        Some(record::Grpc {
+            service: &transaction.grpc_service,
+            method: &transaction.grpc_method,
+            status: &transaction.grpc_status,
+            message: &transaction.grpc_message,
+        })
+    };
+    // Without a Host header or :authority, the name the client asked the server for
+    // in the TLS handshake is the best we have, and then the name it looked up.
+    let host = [
+        &transaction.host,
+        &transaction.sni,
+        &transaction.resolved_name,
+    ]
+    .iter()
+    .find(|name| !name.is_empty())
+    .copied()
+    .unwrap_or(&transaction.host);
+    let path = exporter.template_path(host, &transaction.url);
+    let record = Record {
+        version: record::RECORD_VERSION,
+        kind: "transaction",
+        time: record::ktime_to_ms(transaction.last_ns),
+        protocol: transaction.protocol,
+        tls_library: transaction.tls_library,
+        pid: transaction.tgid,
+        process: transaction
+            .process
+            .as_ref()
+            .map_or("", |process| &process.comm),
+        process_info: transaction.process.as_deref().map(process_info),
+        method: &transaction.method,
+        host,
+        path: &path,
+        status: transaction.status,
+        duration_ms: to_ms(delta_ns),
+        connect_ms: connect_ns.map(to_ms),
+        handshake_ms: handshake_ns.map(to_ms),
+        ttfb_ms: ttfb_ns.map(to_ms),
+        total_ms: to_ms(transaction.last_ns.saturating_sub(first_ns)),
+        request_bytes: transaction.request_bytes,
+        response_bytes: transaction.response_bytes,
+        content_type: &transaction.content_type,
+        grpc,
+        decode_error: &transaction.decode_error,
+        container: transaction
+            .container
+            .as_ref()
+            .map(|container| record::Container {
+                cgroup: &container.cgroup,
+                id: &container.id,
+                runtime: container.runtime,
+                pod: if container.pod_uid.is_empty() {
+                    None
+                } else {
+                    Some(record::Pod {
+                        uid: &container.pod_uid,
+                        name: pod.as_ref().map_or("", |pod| &pod.name),
+                        namespace: pod.as_ref().map_or("", |pod| &pod.namespace),
+                        labels: pod.as_ref().map(|pod| &pod.labels),
+                    })
+                },
+            }),
+        remote_ip: transaction.remote.map(|addr| addr.ip().to_string()),
+        remote_port: transaction.remote.map(|addr| addr.port()),
+        sni: &transaction.sni,
+    };
+    exporter.export(&record);
+}
+
+fn send_dns_line(
+    exporter: &Exporter,
+    event: &TlsEvent,
+    lookup: &dns::Lookup,
+    process: Option<Arc<Process>>,
+) {
+    let status = dns::status_name(lookup.status);
+    let record = DnsRecord {
+        version: record::RECORD_VERSION,
+        kind: "dns",
+        time: record::ktime_to_ms(event.ts),
+        pid: event.tgid,
+        process: process.as_ref().map_or("", |process| &process.comm),
+        process_info: process.as_deref().map(process_info),
+        name: &lookup.name,
+        status: &status,
+        duration_ms: to_ms(event.ts.saturating_sub(lookup.start_ns)),
+        addresses: lookup
+            .addresses
+            .iter()
+            .map(|address| address.to_string())
+            .collect(),
+    };
+    exporter.export_dns(&record);
+}
+
+// The time between two timestamps, if we have both.
+fn span_ns(start_ns: u64, end_ns: u64) -> Option<u64> {
+    if start_ns == 0 || end_ns < start_ns {
+        None
+    } else {
+        Some(end_ns - start_ns)
+    }
+}
+
+fn to_ms(ns: u64) -> f64 {
+    ns as f64 / (1000.0 * 1000.0)
+}
+
+fn library_name(library: &Library) -> &'static str {
+    match library {
+        Library::OpenSsl => "openssl",
+        Library::GnuTls => "gnutls",
+        Library::Nss => "nss",
+        Library::Go => "go",
+        Library::Rustls => "rustls",
+        Library::Jsse => "jsse",
+        Library::Plain => "none",
+        Library::Ktls => "ktls",
+    }
 }
 
-fn is_method(method: &str) -> bool {
-    match method {
-        "GET" => true,
-        "HEAD" => true,
-        "PUT" => true,
-        "POST" => true,
-        &_ => false,
+// We never hear about rustls connections going away, so a new one can turn up at the
+// address of an old one. Its first write tells: the HTTP/2 preface, or a request line
+// where we would not expect one.
+fn is_new_rustls_connection(handle: &Handle, event: &TlsEvent) -> bool {
+    if is_h2_hdr(event) {
+        return true;
+    }
+    if http1::parse_request(captured(event)).is_none() {
+        return false;
+    }
+    handle.is_h2 || handle.last_active.elapsed().as_secs() >= RUSTLS_REUSE_IDLE_SECS
+}
+
+// The connection that a rustls read belongs to. The reader's handle points into the
+// connection, so it is the closest connection of the process that starts before it.
+fn rustls_connection(
+    handles: &HashMap<(u32, u64), Handle>,
+    rustls_readers: &mut HashMap<(u32, u64), (u32, u64)>,
+    event: &TlsEvent,
+) -> (u32, u64) {
+    let reader = (event.tgid, event.handle);
+    if let Some(conn) = rustls_readers.get(&reader) {
+        return *conn;
+    }
+    let conn = handles
+        .iter()
+        .filter(|(&(tgid, conn), h)| {
+            tgid == event.tgid
+                && matches!(h.library, Library::Rustls)
+                && conn <= event.handle
+                && event.handle - conn < MAX_RUSTLS_READER_OFFSET
+        })
+        .map(|(conn, _)| *conn)
+        .max();
+    match conn {
+        Some(conn) => {
+            rustls_readers.insert(reader, conn);
+            conn
+        }
+        None => reader,
+    }
+}
+
+fn process_info(process: &Process) -> record::ProcessInfo<'_> {
+    record::ProcessInfo {
+        exe: &process.exe,
+        argv: &process.argv,
+        uid: process.uid,
+        unit: &process.unit,
+        process_start_time: if process.start_ms == 0 {
+            None
+        } else {
+            Some(process.start_ms)
+        },
     }
 }
 
@@ -255,7 +869,7 @@ const H2_HDR: [u8; H2_HDR_LEN] = [
 ];
 
 fn is_h2_hdr(event: &TlsEvent) -> bool {
-    event.len >= H2_HDR_LEN && event.data[0..24] == H2_HDR
+    captured(event).starts_with(&H2_HDR)
 }
 
 impl Default for Handle {
@@ -263,13 +877,102 @@ impl Default for Handle {
         Handle {
             is_h2: false,
             pid: 0,
+            tgid: 0,
+            process: None,
+            container: None,
+            library: Library::Plain,
+            tls_library: "",
+            last_active: Instant::now(),
+            fd: None,
+            remote: None,
+            looked_up_remote: false,
+            sni: String::from(""),
+            resolved_name: String::from(""),
+            connect_start_ns: 0,
+            connected_ns: 0,
+            handshake_start_ns: 0,
+            handshake_end_ns: 0,
+            reported_setup: false,
+            requests: VecDeque::new(),
+            streams: HashMap::new(),
+            request_headers: http2::HeaderDecoder::new(),
+            response_headers: http2::HeaderDecoder::new(),
+            write_frames: http2::FrameBuffer::new(),
+            read_frames: http2::FrameBuffer::new(),
+        }
+    }
+}
+
+impl Handle {
+    // A connection that was set up by the process that sent `event`.
+    fn new(event: &TlsEvent, containers: &Containers, processes: &mut Processes) -> Handle {
+        Handle {
+            pid: event.pid,
+            tgid: event.tgid,
+            process: processes.get(event.tgid),
+            container: containers.for_process(event.tgid).map(Arc::new),
+            library: event.library.clone(),
+            tls_library: library_name(&event.library),
+            ..Default::default()
+        }
+    }
+
+    // A transaction on this connection that starts at `ts`. The first one gets to
+    // report how the connection was set up.
+    fn new_transaction(&mut self, protocol: &'static str, ts: u64) -> Transaction {
+        let mut transaction = Transaction {
+            protocol,
+            tls_library: self.tls_library,
+            tgid: self.tgid,
+            process: self.process.clone(),
+            container: self.container.clone(),
+            remote: self.remote,
+            sni: self.sni.clone(),
+            resolved_name: self.resolved_name.clone(),
+            start_ns: ts,
+            ..Default::default()
+        };
+        if !self.reported_setup {
+            self.reported_setup = true;
+            transaction.connect_start_ns = self.connect_start_ns;
+            transaction.connected_ns = self.connected_ns;
+            transaction.handshake_start_ns = self.handshake_start_ns;
+            transaction.handshake_end_ns = self.handshake_end_ns;
+        }
+        transaction
+    }
+}
+
+impl Default for Transaction {
+    fn default() -> Transaction {
+        Transaction {
+            protocol: "",
+            tls_library: "",
+            tgid: 0,
+            process: None,
+            container: None,
+            remote: None,
+            sni: String::from(""),
+            resolved_name: String::from(""),
+            connect_start_ns: 0,
+            connected_ns: 0,
+            handshake_start_ns: 0,
+            handshake_end_ns: 0,
             start_ns: 0,
+            first_byte_ns: 0,
             last_ns: 0,
             method: String::from(""),
             url: String::from(""),
             host: String::from(""),
-            streams: HashMap::new(),
-            decoder: h2::hpack::Decoder::new(2048),
+            status: 0,
+            content_type: String::from(""),
+            grpc_service: String::from(""),
+            grpc_method: String::from(""),
+            grpc_status: String::from(""),
+            grpc_message: String::from(""),
+            decode_error: String::from(""),
+            request_bytes: 0,
+            response_bytes: 0,
         }
     }
 }
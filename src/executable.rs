@@ -0,0 +1,203 @@
+// Finding TLS implementations in executables.
+//
+// Libraries are found through the files that processes open, but some programs bring
+// their own TLS implementation: Go's crypto/tls, BoringSSL or OpenSSL linked in
+// statically (Envoy, Chromium based tools) and rustls. We look at the executable
+// of every process that gets started, find these through the symbol table and tell
+// which probes to attach at which file offsets.
+use crate::golang;
+use goblin::elf::program_header::PT_LOAD;
+use goblin::elf::Elf;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+
+// BoringSSL has the same API as OpenSSL for what we need, so these get the probes
+// with the same names.
+const SSL_FNS: [&str; 16] = [
+    "SSL_write_enter",
+    "SSL_write",
+    "SSL_write_ex_enter",
+    "SSL_write_ex",
+    "SSL_read",
+    "SSL_read_ex",
+    "SSL_sendfile",
+    "SSL_set_fd",
+    "SSL_ctrl",
+    "SSL_set_tlsext_host_name",
+    "SSL_do_handshake_enter",
+    "SSL_do_handshake",
+    "SSL_connect_enter",
+    "SSL_connect",
+    "SSL_new",
+    "SSL_free",
+];
+
+// The ends of the mangled names of <rustls::conn::Writer as std::io::Write>::write and
+// <rustls::conn::Reader as std::io::Read>::read. The module path differs between
+// rustls versions, the hash between builds.
+const RUSTLS_WRITE: &str = "Writer$u20$as$u20$std..io..Write$GT$5write17h";
+const RUSTLS_READ: &str = "Reader$u20$as$u20$std..io..Read$GT$4read17h";
+
+pub struct ExeProbes {
+    // What we found, for the log.
+    pub description: String,
+    // Probe names, with the file offsets to attach them at.
+    pub attachments: Vec<(&'static str, Vec<u64>)>,
+}
+
+// Returns None if there is nothing in the executable that we know how to probe. This
+// takes a while for big executables, so better not do it on the runtime thread.
+pub fn inspect(file: &File) -> Result<Option<ExeProbes>, String> {
+    let bytes = match Mapping::new(file)? {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+    let elf = match Elf::parse(&bytes) {
+        Ok(elf) => elf,
+        // Scripts etc.
+        Err(_) => return Ok(None),
+    };
+
+    if let Some(go_tls) = golang::find_go_tls(&elf, &bytes)? {
+        return Ok(Some(ExeProbes {
+            description: format!("Go {}", go_tls.version),
+            attachments: vec![
+                ("go_tls_write", vec![go_tls.write]),
+                ("go_tls_read_enter", vec![go_tls.read]),
+                ("go_tls_read_exit", go_tls.read_returns),
+                ("go_tls_close", vec![go_tls.close]),
+            ],
+        }));
+    }
+
+    let ssl_attachments = SSL_FNS
+        .iter()
+        .map(|name| {
+            (
+                *name,
+                find_offsets(&elf, |sym| sym == probed_function(name)),
+            )
+        })
+        .filter(|(_, offsets)| !offsets.is_empty())
+        .collect::<Vec<(&'static str, Vec<u64>)>>();
+    if !ssl_attachments.is_empty() {
+        return Ok(Some(ExeProbes {
+            description: String::from("statically linked BoringSSL/OpenSSL"),
+            attachments: ssl_attachments,
+        }));
+    }
+
+    let rustls_writes = find_offsets(&elf, |sym| is_rustls(sym, RUSTLS_WRITE));
+    let rustls_reads = find_offsets(&elf, |sym| is_rustls(sym, RUSTLS_READ));
+    if !rustls_writes.is_empty() && !rustls_reads.is_empty() {
+        return Ok(Some(ExeProbes {
+            description: String::from("rustls"),
+            attachments: vec![
+                ("rustls_write", rustls_writes),
+                ("rustls_read", rustls_reads),
+            ],
+        }));
+    }
+
+    Ok(None)
+}
+
+// Probes are named after the function they go on, except for those on the start of a
+// function that we also probe on return, which have "_enter" added.
+pub fn probed_function(probe: &str) -> &str {
+    probe.strip_suffix("_enter").unwrap_or(probe)
+}
+
+fn is_rustls(sym: &str, suffix: &str) -> bool {
+    sym.contains("rustls..") && sym.contains(suffix)
+}
+
+// Address and size of a function.
+pub fn find_symbol(elf: &Elf, name: &str) -> Option<(u64, u64)> {
+    elf.syms
+        .iter()
+        .find(|sym| sym.is_function() && elf.strtab.get_at(sym.st_name) == Some(name))
+        .map(|sym| (sym.st_value, sym.st_size))
+}
+
+// File offsets of the functions that are defined in the executable and whose names
+// match. Functions can be in both the symbol table and the dynamic symbol table.
+fn find_offsets<F>(elf: &Elf, matches: F) -> Vec<u64>
+where
+    F: Fn(&str) -> bool,
+{
+    let syms = elf
+        .syms
+        .iter()
+        .map(|sym| (sym, elf.strtab.get_at(sym.st_name)));
+    let dynsyms = elf
+        .dynsyms
+        .iter()
+        .map(|sym| (sym, elf.dynstrtab.get_at(sym.st_name)));
+    let mut offsets = syms
+        .chain(dynsyms)
+        // Functions that are imported from a library are undefined here.
+        .filter(|(sym, _)| sym.is_function() && sym.st_shndx != 0 && sym.st_value != 0)
+        .filter(|(_, name)| name.map_or(false, &matches))
+        .filter_map(|(sym, _)| file_offset(elf, sym.st_value))
+        .collect::<Vec<u64>>();
+    offsets.sort_unstable();
+    offsets.dedup();
+    offsets
+}
+
+pub fn file_offset(elf: &Elf, vaddr: u64) -> Option<u64> {
+    elf.program_headers
+        .iter()
+        .find(|ph| ph.p_type == PT_LOAD && vaddr >= ph.p_vaddr && vaddr < ph.p_vaddr + ph.p_filesz)
+        .map(|ph| vaddr - ph.p_vaddr + ph.p_offset)
+}
+
+// A read-only mapping of a whole file, so we only read the parts we look at. Running
+// executables cannot be written to, so they stay put while we look.
+pub struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+impl Mapping {
+    // None for an empty file, which cannot be mapped.
+    pub fn new(file: &File) -> Result<Option<Mapping>, String> {
+        let len = file.metadata().map_err(|err| err.to_string())?.len() as usize;
+        if len == 0 {
+            return Ok(None);
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error().to_string());
+        }
+        Ok(Some(Mapping { ptr, len }))
+    }
+}
+
+impl Deref for Mapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
@@ -0,0 +1,103 @@
+// Sends records to Orchestrator.
+//
+// Transactions either go out one by one, or, with an aggregation window, as
+// per-window summaries (see aggregate.rs).
+use crate::aggregate::Aggregator;
+use crate::path_template::PathTemplater;
+use crate::record::DnsRecord;
+use crate::record::Format;
+use crate::record::Record;
+use crate::sink::Sink;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// How long we wait for queued records to go out when shutting down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Exporter {
+    sink: Sink,
+    format: Format,
+    aggregator: Option<Mutex<Aggregator>>,
+    templater: PathTemplater,
+}
+
+// Start the exporter. With a window, transactions are aggregated and flushed
+// every window.
+pub fn start_exporter(
+    sink: Sink,
+    format: Format,
+    window: Option<Duration>,
+    templater: PathTemplater,
+) -> Arc<Exporter> {
+    let exporter = Arc::new(Exporter {
+        sink,
+        format,
+        aggregator: window.map(|_| Mutex::new(Aggregator::new())),
+        templater,
+    });
+    if let Some(window) = window {
+        let flusher = exporter.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(window);
+            // The first tick is immediate.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                flusher.flush();
+            }
+        });
+    }
+    exporter
+}
+
+impl Exporter {
+    // The path as we report it, see path_template.rs.
+    pub fn template_path(&self, host: &str, url: &str) -> String {
+        self.templater.template(host, url)
+    }
+
+    pub fn export(&self, record: &Record) {
+        if let Some(aggregator) = &self.aggregator {
+            aggregator.lock().unwrap().add(record);
+            return;
+        }
+        let msg = record.encode(self.format);
+        println!("++ seen {}", msg);
+        self.sink.send(msg.into_bytes());
+    }
+
+    // The legacy format has no room for DNS lookups.
+    pub fn export_dns(&self, record: &DnsRecord) {
+        if self.format == Format::LegacyTsv {
+            return;
+        }
+        if let Some(aggregator) = &self.aggregator {
+            aggregator.lock().unwrap().add_dns(record);
+            return;
+        }
+        let msg = record.encode();
+        println!("++ seen {}", msg);
+        self.sink.send(msg.into_bytes());
+    }
+
+    // Send out everything that was aggregated so far.
+    pub fn flush(&self) {
+        if let Some(aggregator) = &self.aggregator {
+            aggregator.lock().unwrap().flush(|msg| {
+                println!("++ aggregated {}", msg);
+                self.sink.send(msg.into_bytes());
+            });
+        }
+    }
+
+    // Flush and give the sink a chance to deliver what it has queued.
+    pub async fn shutdown(&self) {
+        self.flush();
+        self.sink.drain(SHUTDOWN_TIMEOUT).await;
+    }
+
+    pub fn log_stats(&self) {
+        self.sink.log_stats();
+    }
+}
@@ -0,0 +1,147 @@
+// Which file a library or executable really is.
+//
+// We probe every file once, however many processes use it, so we need to tell when two
+// processes use the same file. Device and inode would do, but the overlay filesystems
+// that containers run on give every container a device of its own, also for the files
+// from image layers that containers share. Uprobes go on the file underneath, which is
+// also the one that /proc/<pid>/maps shows for a mapping of the file. So we map the
+// file ourselves, and look in our own maps.
+use crate::executable::Mapping;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::fs::File;
+use std::os::unix::fs::MetadataExt;
+
+// Going through our maps takes a while, so we remember what we found, by device and
+// inode as stat() has them. Up to a point.
+const MAX_IDS: usize = 4096;
+
+pub type FileId = (u64, u64);
+
+pub struct FileIds {
+    by_stat: HashMap<FileId, FileId>,
+}
+
+impl FileIds {
+    pub fn new() -> FileIds {
+        FileIds {
+            by_stat: HashMap::new(),
+        }
+    }
+
+    // Device and inode of the file underneath, or as stat() has them if we can't tell.
+    // None if the file is not there.
+    pub fn id(&mut self, file: &File) -> Option<FileId> {
+        let metadata = file.metadata().ok()?;
+        let stat_id = (metadata.dev(), metadata.ino());
+        if let Some(id) = self.by_stat.get(&stat_id) {
+            return Some(*id);
+        }
+        if self.by_stat.len() >= MAX_IDS {
+            self.by_stat.clear();
+        }
+        let id = mapped_id(file).unwrap_or(stat_id);
+        self.by_stat.insert(stat_id, id);
+        Some(id)
+    }
+}
+
+fn mapped_id(file: &File) -> Option<FileId> {
+    let mapping = Mapping::new(file).ok()??;
+    let maps = fs::read_to_string("/proc/self/maps").ok()?;
+    let start = format!("{:08x}-", mapping.as_ptr() as usize);
+    let line = maps.lines().find(|line| line.starts_with(&start))?;
+    map_id(line)
+}
+
+// The files that processes have mapped right now.
+pub fn mapped_files() -> HashSet<FileId> {
+    let mut ids = HashSet::new();
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return ids,
+    };
+    for entry in entries.flatten() {
+        let pid = entry.file_name();
+        if !pid.to_string_lossy().bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        // Processes exit while we look.
+        if let Ok(maps) = fs::read_to_string(entry.path().join("maps")) {
+            ids.extend(maps.lines().filter_map(map_id));
+        }
+    }
+    ids
+}
+
+// A line from a maps file is address, perms, offset, dev, inode and path, with the
+// device as hex "major:minor". Anonymous mappings have inode 0.
+fn map_id(line: &str) -> Option<FileId> {
+    let mut fields = line.split_ascii_whitespace().skip(3);
+    let (major, minor) = fields.next()?.split_once(':')?;
+    let major = u32::from_str_radix(major, 16).ok()?;
+    let minor = u32::from_str_radix(minor, 16).ok()?;
+    let ino = fields.next()?.parse().ok()?;
+    if ino == 0 {
+        return None;
+    }
+    Some((libc::makedev(major, minor) as u64, ino))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Write;
+
+    // Outside of overlay filesystems, this is what stat() says.
+    #[test]
+    fn plain_files() {
+        let path = env::temp_dir().join(format!("file_id_test_{}", std::process::id()));
+        File::create(&path)
+            .and_then(|mut file| file.write_all(b"\x7fELF"))
+            .unwrap();
+        let file = File::open(&path).unwrap();
+        let metadata = file.metadata().unwrap();
+        assert_eq!(mapped_id(&file), Some((metadata.dev(), metadata.ino())));
+        let mut ids = FileIds::new();
+        assert_eq!(ids.id(&file), Some((metadata.dev(), metadata.ino())));
+        assert_eq!(ids.id(&file), Some((metadata.dev(), metadata.ino())));
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn map_lines() {
+        assert_eq!(
+            map_id("7f2a1c000000-7f2a1c028000 r--p 00000000 00:1f 1317  /usr/lib/libssl.so.3"),
+            Some((libc::makedev(0, 0x1f) as u64, 1317))
+        );
+        assert_eq!(
+            map_id("7f2a1c028000-7f2a1c02a000 rw-p 00000000 103:02 0"),
+            None
+        );
+        assert_eq!(map_id("garbage"), None);
+    }
+
+    // We are a process too.
+    #[test]
+    fn own_mappings() {
+        let exe = File::open("/proc/self/exe").unwrap();
+        let id = FileIds::new().id(&exe).unwrap();
+        assert!(mapped_files().contains(&id));
+    }
+
+    // Empty files cannot be mapped.
+    #[test]
+    fn empty_files() {
+        let path = env::temp_dir().join(format!("file_id_empty_{}", std::process::id()));
+        let file = File::create(&path).unwrap();
+        let metadata = file.metadata().unwrap();
+        assert_eq!(mapped_id(&file), None);
+        assert_eq!(
+            FileIds::new().id(&file),
+            Some((metadata.dev(), metadata.ino()))
+        );
+        fs::remove_file(&path).ok();
+    }
+}
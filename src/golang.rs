@@ -0,0 +1,158 @@
+// Finding crypto/tls in Go executables.
+//
+// Go links everything statically, so there is no library for us to see being opened.
+// Instead, we look at every executable that gets started and check whether it is a
+// Go binary that contains crypto/tls. If so, we attach to the functions in the binary
+// itself, at file offsets that we work out here.
+//
+// Two things make Go special:
+// - Since Go 1.17, functions take their arguments in registers (RAX, RBX, RCX, ...)
+//   and not on the stack. Older binaries are not supported.
+// - Goroutine stacks get moved around, which does not go well with uretprobes as they
+//   patch the return address on the stack. So for Read, which we need to see the end
+//   of, we put a probe on every RET instruction in the function instead.
+use crate::executable;
+use goblin::elf::header::EM_X86_64;
+use goblin::elf::Elf;
+use iced_x86::Decoder;
+use iced_x86::DecoderOptions;
+use iced_x86::Mnemonic;
+
+const WRITE_FN: &str = "crypto/tls.(*Conn).Write";
+const READ_FN: &str = "crypto/tls.(*Conn).Read";
+const CLOSE_FN: &str = "crypto/tls.(*Conn).Close";
+
+const BUILDINFO_MAGIC: &[u8] = b"\xff Go buildinf:";
+
+// The first version with the register based calling convention on amd64.
+const MIN_MINOR_VERSION: u32 = 17;
+
+// File offsets to attach to.
+pub struct GoTls {
+    pub version: String,
+    pub write: u64,
+    pub read: u64,
+    pub read_returns: Vec<u64>,
+    pub close: u64,
+}
+
+// Look for crypto/tls in an executable. Returns None if it is not a Go binary or
+// if it does not use crypto/tls, and an error if it is one that we cannot handle.
+pub fn find_go_tls(elf: &Elf, bytes: &[u8]) -> Result<Option<GoTls>, String> {
+    let is_go = elf.section_headers.iter().any(|sh| {
+        matches!(
+            elf.shdr_strtab.get_at(sh.sh_name),
+            Some(".go.buildinfo") | Some(".gopclntab")
+        )
+    });
+    if !is_go {
+        return Ok(None);
+    }
+
+    let write = match executable::find_symbol(elf, WRITE_FN) {
+        Some(write) => write,
+        None => {
+            if elf.syms.is_empty() {
+                return Err("no symbol table, binary is stripped".to_string());
+            }
+            return Ok(None);
+        }
+    };
+    let read = executable::find_symbol(elf, READ_FN).ok_or(format!("{} not found", READ_FN))?;
+    let close = executable::find_symbol(elf, CLOSE_FN).ok_or(format!("{} not found", CLOSE_FN))?;
+
+    if elf.header.e_machine != EM_X86_64 {
+        return Err("only amd64 is supported".to_string());
+    }
+    let version = go_version(elf, bytes).unwrap_or_default();
+    if !has_register_abi(&version) {
+        return Err(format!("{} passes arguments on the stack", version));
+    }
+
+    let read_offset = executable::file_offset(elf, read.0).ok_or("Read not in a loaded segment")?;
+    let code = bytes
+        .get(read_offset as usize..(read_offset + read.1) as usize)
+        .ok_or("Read outside of the file")?;
+    let mut decoder = Decoder::with_ip(64, code, read.0, DecoderOptions::NONE);
+    let read_returns = decoder
+        .iter()
+        .filter(|instr| instr.mnemonic() == Mnemonic::Ret)
+        .map(|instr| read_offset + (instr.ip() - read.0))
+        .collect::<Vec<u64>>();
+    if read_returns.is_empty() {
+        return Err("no return from Read found".to_string());
+    }
+
+    Ok(Some(GoTls {
+        version,
+        write: executable::file_offset(elf, write.0).ok_or("Write not in a loaded segment")?,
+        read: read_offset,
+        read_returns,
+        close: executable::file_offset(elf, close.0).ok_or("Close not in a loaded segment")?,
+    }))
+}
+
+// The Go version from the build info, like "go1.19.4".
+fn go_version(elf: &Elf, bytes: &[u8]) -> Option<String> {
+    let sh = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".go.buildinfo"))?;
+    let info = bytes.get(sh.sh_offset as usize..(sh.sh_offset + sh.sh_size) as usize)?;
+    if info.len() < 32 || !info.starts_with(BUILDINFO_MAGIC) {
+        return None;
+    }
+    let ptr_size = info[14] as usize;
+    let flags = info[15];
+    if flags & 0x2 != 0 {
+        // Go 1.18 and later: the version string follows the header, prefixed by its
+        // length as a varint.
+        let (len, used) = read_varint(&info[32..])?;
+        let start = 32 + used;
+        let version = info.get(start..start + len as usize)?;
+        return Some(String::from_utf8_lossy(version).to_string());
+    }
+    // Before that: a pointer to a Go string (pointer and length) for the version.
+    if ptr_size != 8 || flags & 0x1 != 0 {
+        return None;
+    }
+    let string_header = read_u64(info, 16)?;
+    let header = bytes.get(executable::file_offset(elf, string_header)? as usize..)?;
+    let data = executable::file_offset(elf, read_u64(header, 0)?)? as usize;
+    let len = read_u64(header, 8)? as usize;
+    let version = bytes.get(data..data + len)?;
+    Some(String::from_utf8_lossy(version).to_string())
+}
+
+fn has_register_abi(version: &str) -> bool {
+    if version.starts_with("devel") {
+        return true;
+    }
+    // "go1.17", "go1.19.4", "go1.21rc2"
+    let minor = version
+        .strip_prefix("go1.")
+        .map(|rest| {
+            rest.chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect::<String>()
+        })
+        .and_then(|minor| minor.parse::<u32>().ok());
+    matches!(minor, Some(minor) if minor >= MIN_MINOR_VERSION)
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let mut value = [0u8; 8];
+    value.copy_from_slice(bytes.get(offset..offset + 8)?);
+    Some(u64::from_le_bytes(value))
+}
+
+fn read_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (idx, byte) in bytes.iter().take(10).enumerate() {
+        value |= ((byte & 0x7f) as u64) << (7 * idx);
+        if byte & 0x80 == 0 {
+            return Some((value, idx + 1));
+        }
+    }
+    None
+}
@@ -0,0 +1,50 @@
+// gRPC runs over HTTP/2 with some conventions on top that we need to know
+// about to make sense of the calls: the path names the service and the method,
+// and the outcome of a call is in the trailers, not in the HTTP status.
+
+// "application/grpc", optionally followed by "+proto", "+json", etc.
+pub fn is_grpc(content_type: &str) -> bool {
+    let lower = content_type.to_ascii_lowercase();
+    lower == "application/grpc"
+        || lower.starts_with("application/grpc+")
+        || lower.starts_with("application/grpc;")
+}
+
+// Split "/package.Service/Method" into service and method.
+pub fn split_path(path: &str) -> Option<(String, String)> {
+    let mut parts = path.strip_prefix('/')?.splitn(2, '/');
+    let service = parts.next()?;
+    let method = parts.next()?;
+    if service.is_empty() || method.is_empty() || method.contains('/') {
+        return None;
+    }
+    Some((service.to_string(), method.to_string()))
+}
+
+// The grpc-message trailer is percent-encoded.
+pub fn decode_message(message: &[u8]) -> String {
+    let mut decoded = Vec::with_capacity(message.len());
+    let mut i = 0;
+    while i < message.len() {
+        if message[i] == b'%'
+            && i + 2 < message.len()
+            && message[i + 1].is_ascii_hexdigit()
+            && message[i + 2].is_ascii_hexdigit()
+        {
+            decoded.push(hex_value(message[i + 1]) << 4 | hex_value(message[i + 2]));
+            i += 3;
+        } else {
+            decoded.push(message[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn hex_value(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
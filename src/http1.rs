@@ -0,0 +1,313 @@
+// HTTP/1.1 message parsing.
+//
+// We only ever see copies of what goes through SSL_read/SSL_write, split up however
+// the application felt like calling the library, and truncated to what fits in a
+// TlsEvent. So everything in here is incremental, and when we lose track of where we
+// are in a message we give up on that message instead of guessing.
+
+// Status lines and header lines longer than this are not something we want to keep
+// in memory; we give up on the response instead.
+const MAX_LINE_LEN: usize = 8192;
+
+pub struct Request {
+    pub method: String,
+    pub url: String,
+    pub host: String,
+}
+
+// Try to parse the start of a request. We only look at the request line
+// and the headers that come in the same write; anything else is body.
+pub fn parse_request(data: &[u8]) -> Option<Request> {
+    let buf = String::from_utf8_lossy(data);
+    let mut lines = buf.lines();
+    let elems: Vec<&str> = lines.next()?.split_ascii_whitespace().collect();
+    if elems.len() != 3 || !is_method(elems[0]) || !elems[2].starts_with("HTTP/") {
+        return None;
+    }
+    let mut request = Request {
+        method: String::from(elems[0]),
+        url: String::from(elems[1]),
+        host: String::from(""),
+    };
+    for line in lines {
+        if line.is_empty() {
+            break;
+        }
+        if line.to_ascii_lowercase().starts_with("host: ") {
+            request.host = (&line[6..]).to_string();
+        }
+    }
+    Some(request)
+}
+
+// The same methods as is_http_start() in the probes, which picks plaintext sockets.
+fn is_method(method: &str) -> bool {
+    match method {
+        "GET" => true,
+        "HEAD" => true,
+        "PUT" => true,
+        "POST" => true,
+        "DELETE" => true,
+        "PATCH" => true,
+        "OPTIONS" => true,
+        "CONNECT" => true,
+        "TRACE" => true,
+        &_ => false,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    // Status line and headers.
+    Head,
+    // Content-Length framed body, with the number of bytes still to come.
+    Body(usize),
+    // Chunked body, waiting for a chunk size line.
+    ChunkSize,
+    // Chunked body, with the number of bytes (including the trailing CRLF)
+    // still to come for the current chunk.
+    ChunkData(usize),
+    // Chunked body, waiting for the empty line after the trailers.
+    Trailers,
+    // No framing information, body runs until the connection is closed.
+    UntilClose,
+    // We lost track of the message, typically because a line we needed
+    // was in the part of a read that did not fit in the event.
+    Lost,
+    Done,
+}
+
+// Incremental parser for a single response. Feed it the reads that come in
+// after the request was sent and it will tell when the response is complete.
+pub struct Response {
+    pub status: u16,
+    pub content_type: String,
+    // Bytes on the wire for this response, status line and headers included.
+    pub bytes: usize,
+    state: State,
+    line: Vec<u8>,
+    no_body: bool,
+    chunked: bool,
+    content_length: Option<usize>,
+}
+
+impl Response {
+    // A response to a HEAD request never has a body, whatever the headers
+    // say, so we need to know about that up front.
+    pub fn new(is_head: bool) -> Response {
+        Response {
+            status: 0,
+            content_type: String::from(""),
+            bytes: 0,
+            state: State::Head,
+            line: Vec::new(),
+            no_body: is_head,
+            chunked: false,
+            content_length: None,
+        }
+    }
+
+    // Whether we know that the response is complete.
+    pub fn is_complete(&self) -> bool {
+        self.state == State::Done
+    }
+
+    // Whether we have seen any of the response yet.
+    pub fn is_started(&self) -> bool {
+        self.bytes > 0
+    }
+
+    // Whether we know where the response ends. If not, the end of the response
+    // is signalled by the end of the connection or by the next request.
+    pub fn is_delimited(&self) -> bool {
+        self.state != State::UntilClose && self.state != State::Lost
+    }
+
+    // Process a read. `data` is what we captured, `len` is the actual length of the read,
+    // which may be more. Returns how many bytes of the read belong to this response; the
+    // rest, if any, is the start of the next response.
+    pub fn feed(&mut self, data: &[u8], len: usize) -> usize {
+        let mut pos = 0;
+        while pos < len && self.state != State::Done {
+            match self.state {
+                State::Head | State::ChunkSize | State::Trailers => {
+                    if pos >= data.len() {
+                        // We need to see the line but it is in the part that did not get captured.
+                        self.state = State::Lost;
+                        continue;
+                    }
+                    match data[pos..].iter().position(|&b| b == b'\n') {
+                        Some(idx) => {
+                            self.line.extend_from_slice(&data[pos..pos + idx + 1]);
+                            pos += idx + 1;
+                            self.bytes += idx + 1;
+                            self.process_line();
+                        }
+                        None => {
+                            self.line.extend_from_slice(&data[pos..]);
+                            self.bytes += data.len() - pos;
+                            pos = data.len();
+                            if self.line.len() > MAX_LINE_LEN {
+                                self.state = State::Lost;
+                            }
+                        }
+                    }
+                }
+                State::Body(remaining) => {
+                    let count = remaining.min(len - pos);
+                    pos += count;
+                    self.bytes += count;
+                    self.state = if count == remaining {
+                        State::Done
+                    } else {
+                        State::Body(remaining - count)
+                    };
+                }
+                State::ChunkData(remaining) => {
+                    let count = remaining.min(len - pos);
+                    pos += count;
+                    self.bytes += count;
+                    self.state = if count == remaining {
+                        State::ChunkSize
+                    } else {
+                        State::ChunkData(remaining - count)
+                    };
+                }
+                State::UntilClose | State::Lost => {
+                    self.bytes += len - pos;
+                    pos = len;
+                }
+                State::Done => {}
+            }
+        }
+        pos
+    }
+
+    fn process_line(&mut self) {
+        let line = String::from_utf8_lossy(&self.line).trim_end().to_string();
+        self.line.clear();
+        match self.state {
+            State::Head if self.status == 0 => {
+                // "HTTP/1.1 200 OK"
+                let elems: Vec<&str> = line.splitn(3, ' ').collect();
+                self.status = if elems.len() >= 2 && elems[0].starts_with("HTTP/") {
+                    elems[1].parse::<u16>().unwrap_or(0)
+                } else {
+                    0
+                };
+                if self.status == 0 {
+                    self.state = State::Lost;
+                }
+            }
+            State::Head if line.is_empty() => self.end_of_head(),
+            State::Head => {
+                let lower = line.to_ascii_lowercase();
+                if let Some(value) = lower.strip_prefix("content-length:") {
+                    self.content_length = value.trim().parse::<usize>().ok();
+                } else if lower.starts_with("content-type:") {
+                    self.content_type = line[13..].trim().to_string();
+                } else if let Some(value) = lower.strip_prefix("transfer-encoding:") {
+                    self.chunked = value.trim().ends_with("chunked");
+                }
+            }
+            State::ChunkSize => {
+                let size = line.split(';').next().unwrap_or("").trim();
+                self.state = match usize::from_str_radix(size, 16) {
+                    Ok(0) => State::Trailers,
+                    Ok(size) => State::ChunkData(size + 2),
+                    Err(_) => State::Lost,
+                };
+            }
+            State::Trailers if line.is_empty() => self.state = State::Done,
+            _ => {}
+        }
+    }
+
+    fn end_of_head(&mut self) {
+        self.state = match self.status {
+            // Switching protocols; whatever follows is not HTTP/1.1 anymore.
+            101 => State::UntilClose,
+            // Interim responses like "100 Continue" are followed by the real one.
+            100..=199 => {
+                self.status = 0;
+                self.chunked = false;
+                self.content_length = None;
+                self.content_type.clear();
+                State::Head
+            }
+            204 | 304 => State::Done,
+            _ if self.no_body => State::Done,
+            _ if self.chunked => State::ChunkSize,
+            _ => match self.content_length {
+                Some(0) => State::Done,
+                Some(len) => State::Body(len),
+                None => State::UntilClose,
+            },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_all_methods() {
+        for method in ["GET", "HEAD", "PUT", "POST", "DELETE", "PATCH", "OPTIONS"] {
+            let line = format!("{} /items/1 HTTP/1.1\r\nHost: example.com\r\n\r\n", method);
+            let request = parse_request(line.as_bytes()).unwrap();
+            assert_eq!(request.method, method);
+            assert_eq!(request.url, "/items/1");
+            assert_eq!(request.host, "example.com");
+        }
+        assert!(parse_request(b"FROB /items/1 HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse_request(b"{\"id\": 1}").is_none());
+    }
+
+    #[test]
+    fn pipelined_delete_and_patch() {
+        // Written back to back on a keep-alive connection, both are requests of their own.
+        let delete = parse_request(b"DELETE /items/1 HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        let patch =
+            parse_request(b"PATCH /items/2 HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\n{}")
+                .unwrap();
+        assert_eq!(delete.method, "DELETE");
+        assert_eq!(patch.method, "PATCH");
+
+        // And their responses come in a single read.
+        let first = b"HTTP/1.1 204 No Content\r\n\r\n";
+        let second = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}";
+        let data = [&first[..], &second[..]].concat();
+        let mut response = Response::new(false);
+        let consumed = response.feed(&data, data.len());
+        assert_eq!(consumed, first.len());
+        assert!(response.is_complete());
+        assert_eq!(response.status, 204);
+
+        let mut response = Response::new(false);
+        let rest = &data[consumed..];
+        assert_eq!(response.feed(rest, rest.len()), second.len());
+        assert!(response.is_complete());
+        assert_eq!(response.status, 200);
+        assert_eq!(response.bytes, second.len());
+    }
+
+    #[test]
+    fn chunked_response_split_over_reads() {
+        let mut response = Response::new(false);
+        let head = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nab";
+        assert_eq!(response.feed(head, head.len()), head.len());
+        assert!(!response.is_complete());
+        let tail = b"cd\r\n0\r\n\r\n";
+        assert_eq!(response.feed(tail, tail.len()), tail.len());
+        assert!(response.is_complete());
+    }
+
+    #[test]
+    fn head_response_has_no_body() {
+        let mut response = Response::new(true);
+        let data = b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n";
+        assert_eq!(response.feed(data, data.len()), data.len());
+        assert!(response.is_complete());
+    }
+}
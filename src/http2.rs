@@ -0,0 +1,300 @@
+// HTTP/2 frame handling.
+//
+// The application reads and writes whatever amount of bytes it likes, so frames
+// can be spread over several events and one event can hold several frames. On top
+// of that, we only capture the start of large reads and writes. We reassemble
+// frames per direction here and make the best of the parts we did not capture.
+use bytes::BytesMut;
+use h2::frame::Head;
+use h2::frame::Kind;
+use h2::frame::HEADER_LEN;
+use std::fmt;
+
+// Frames larger than this are not kept around in full; we hand out the start and
+// skip the rest. The default maximum frame size is 16k and header blocks are a lot
+// smaller than this in practice.
+const MAX_BUFFERED_FRAME: usize = 64 * 1024;
+
+// When we are out of sync, we take anything that starts with a known frame type
+// and a sensible length as the start of a frame.
+const MAX_PLAUSIBLE_FRAME: usize = 1 << 20;
+
+// The HPACK dynamic table starts out at the default size (RFC 7540, section 6.5.2)
+// and the encoder tells us in-band when it changes. Whether a change is allowed is
+// between the peers, so we accept anything up to the maximum a SETTINGS frame can
+// carry.
+const HPACK_TABLE_SIZE: usize = 4096;
+const MAX_HPACK_TABLE_SIZE: usize = u32::MAX as usize;
+
+// Same as what the h2 crate uses by default.
+const MAX_HEADER_LIST_SIZE: usize = 16 << 20;
+
+// We stop collecting CONTINUATION frames when a header block gets larger than this.
+const MAX_HEADER_BLOCK: usize = 1 << 20;
+
+const END_STREAM: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+
+pub struct Frame {
+    pub head: Head,
+    pub payload: BytesMut,
+    // False if we only have the start of the payload.
+    pub is_complete: bool,
+}
+
+pub struct FrameBuffer {
+    buf: BytesMut,
+    // Bytes of the current frame that we did not capture and that we still need to skip.
+    skip: usize,
+    // Whether we know where the next frame starts.
+    in_sync: bool,
+}
+
+impl FrameBuffer {
+    pub fn new() -> FrameBuffer {
+        FrameBuffer {
+            buf: BytesMut::new(),
+            skip: 0,
+            in_sync: true,
+        }
+    }
+
+    // Process a read or write. `data` is what we captured, `len` is the actual length,
+    // which may be more. Returns all frames that we could find.
+    pub fn feed(&mut self, data: &[u8], len: usize) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let mut data = data;
+        let mut len = len;
+
+        if self.skip > 0 {
+            let count = self.skip.min(len);
+            self.skip -= count;
+            data = &data[count.min(data.len())..];
+            len -= count;
+        }
+        if len == 0 {
+            return frames;
+        }
+        if !self.in_sync {
+            if !is_plausible_head(data) {
+                return frames;
+            }
+            println!("HTTP/2 frame stream back in sync");
+            self.in_sync = true;
+        }
+
+        self.buf.extend_from_slice(data);
+        let mut missing = len - data.len();
+        while self.buf.len() >= HEADER_LEN {
+            let frame_len = HEADER_LEN + payload_len(&self.buf);
+            if self.buf.len() >= frame_len {
+                let mut payload = self.buf.split_to(frame_len);
+                let head = Head::parse(&payload.split_to(HEADER_LEN));
+                frames.push(Frame {
+                    head,
+                    payload,
+                    is_complete: true,
+                });
+            } else if missing > 0 || frame_len > MAX_BUFFERED_FRAME {
+                // We are not going to see this frame in full. Hand out what we have
+                // and skip over the rest.
+                let mut payload = self.buf.split();
+                let head = Head::parse(&payload.split_to(HEADER_LEN));
+                frames.push(Frame {
+                    head,
+                    payload,
+                    is_complete: false,
+                });
+                let rest = frame_len - HEADER_LEN - frames.last().unwrap().payload.len();
+                let count = rest.min(missing);
+                missing -= count;
+                self.skip = rest - count;
+            } else {
+                break;
+            }
+        }
+        if missing > 0 {
+            // The start of a frame is in the part we did not capture. There is
+            // no telling where the next one starts.
+            println!(
+                "HTTP/2 frame stream out of sync, {} bytes not captured",
+                missing
+            );
+            self.buf.clear();
+            self.in_sync = false;
+        }
+        frames
+    }
+}
+
+fn payload_len(buf: &[u8]) -> usize {
+    (buf[0] as usize) << 16 | (buf[1] as usize) << 8 | buf[2] as usize
+}
+
+fn is_plausible_head(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN
+        && payload_len(data) <= MAX_PLAUSIBLE_FRAME
+        && Head::parse(data).kind() != Kind::Unknown
+        && data[5] & 0x80 == 0
+}
+
+// Collects header blocks, which can be split over a HEADERS or PUSH_PROMISE frame and
+// any number of CONTINUATION frames, and decodes them. There is one of these per
+// direction, as each direction has its own HPACK dynamic table.
+pub struct HeaderDecoder {
+    decoder: h2::hpack::Decoder,
+    partial: Option<(HeaderFrame, BytesMut)>,
+    // The stream of the HEADERS block we are working on, and whether it ends the stream.
+    block: Option<(u32, bool)>,
+}
+
+enum HeaderFrame {
+    Headers(h2::frame::Headers),
+    PushPromise(h2::frame::PushPromise),
+}
+
+#[derive(Debug)]
+pub enum HeaderError {
+    // Part of a header block was not captured.
+    Truncated,
+    // A header block that is larger than we are willing to collect.
+    TooLarge,
+    // A CONTINUATION frame where we did not expect one, or the lack of one.
+    UnexpectedContinuation,
+    // The header block was decoded, but it is not a valid request or response.
+    Malformed,
+    Frame(h2::frame::Error),
+}
+
+impl HeaderError {
+    // Whether the dynamic table is out of sync after this error. If so, further
+    // decoding on the connection cannot be trusted.
+    pub fn is_fatal(&self) -> bool {
+        !matches!(self, HeaderError::Malformed)
+    }
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::Truncated => write!(f, "header block not fully captured"),
+            HeaderError::TooLarge => write!(f, "header block too large"),
+            HeaderError::UnexpectedContinuation => write!(f, "unexpected CONTINUATION"),
+            HeaderError::Malformed => write!(f, "malformed header block"),
+            HeaderError::Frame(err) => write!(f, "{:?}", err),
+        }
+    }
+}
+
+impl HeaderDecoder {
+    pub fn new() -> HeaderDecoder {
+        let mut decoder = h2::hpack::Decoder::new(HPACK_TABLE_SIZE);
+        decoder.queue_size_update(MAX_HPACK_TABLE_SIZE);
+        HeaderDecoder {
+            decoder,
+            partial: None,
+            block: None,
+        }
+    }
+
+    // Process a frame. When this completes a HEADERS header block, the decoded frame is returned.
+    pub fn process(&mut self, frame: Frame) -> Result<Option<h2::frame::Headers>, HeaderError> {
+        let result = self.process_frame(frame);
+        if result.is_ok() && self.partial.is_none() {
+            self.block = None;
+        }
+        result
+    }
+
+    // After an error, the stream of the HEADERS block that we could not decode, and
+    // whether it ended the stream. The stream still goes on, or ends, without it.
+    pub fn take_failed_block(&mut self) -> Option<(u32, bool)> {
+        self.block.take()
+    }
+
+    fn process_frame(&mut self, frame: Frame) -> Result<Option<h2::frame::Headers>, HeaderError> {
+        let head = frame.head;
+        match head.kind() {
+            Kind::Headers | Kind::PushPromise => {
+                self.block = if head.kind() == Kind::Headers {
+                    Some((head.stream_id().value(), head.flag() & END_STREAM != 0))
+                } else {
+                    None
+                };
+                if self.partial.take().is_some() {
+                    return Err(HeaderError::UnexpectedContinuation);
+                }
+                if !frame.is_complete {
+                    return Err(HeaderError::Truncated);
+                }
+                let (block, fragment) = if head.kind() == Kind::Headers {
+                    let (headers, fragment) = h2::frame::Headers::load(head, frame.payload)
+                        .map_err(HeaderError::Frame)?;
+                    (HeaderFrame::Headers(headers), fragment)
+                } else {
+                    let (promise, fragment) = h2::frame::PushPromise::load(head, frame.payload)
+                        .map_err(HeaderError::Frame)?;
+                    (HeaderFrame::PushPromise(promise), fragment)
+                };
+                self.collect(head, block, fragment)
+            }
+            Kind::Continuation => {
+                let (block, mut fragment) = self
+                    .partial
+                    .take()
+                    .ok_or(HeaderError::UnexpectedContinuation)?;
+                if !frame.is_complete {
+                    return Err(HeaderError::Truncated);
+                }
+                if block.stream_id() != head.stream_id() {
+                    return Err(HeaderError::UnexpectedContinuation);
+                }
+                fragment.extend_from_slice(&frame.payload);
+                self.collect(head, block, fragment)
+            }
+            _ => {
+                // Nothing can come in between the frames of a header block.
+                if self.partial.take().is_some() {
+                    return Err(HeaderError::UnexpectedContinuation);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    fn collect(
+        &mut self,
+        head: Head,
+        block: HeaderFrame,
+        mut fragment: BytesMut,
+    ) -> Result<Option<h2::frame::Headers>, HeaderError> {
+        if head.flag() & END_HEADERS == 0 {
+            if fragment.len() > MAX_HEADER_BLOCK {
+                return Err(HeaderError::TooLarge);
+            }
+            self.partial = Some((block, fragment));
+            return Ok(None);
+        }
+        let result = match block {
+            HeaderFrame::Headers(mut headers) => headers
+                .load_hpack(&mut fragment, MAX_HEADER_LIST_SIZE, &mut self.decoder)
+                .map(|_| Some(headers)),
+            HeaderFrame::PushPromise(mut promise) => promise
+                .load_hpack(&mut fragment, MAX_HEADER_LIST_SIZE, &mut self.decoder)
+                .map(|_| None),
+        };
+        result.map_err(|err| match err {
+            h2::frame::Error::MalformedMessage => HeaderError::Malformed,
+            err => HeaderError::Frame(err),
+        })
+    }
+}
+
+impl HeaderFrame {
+    fn stream_id(&self) -> h2::frame::StreamId {
+        match self {
+            HeaderFrame::Headers(headers) => headers.stream_id(),
+            HeaderFrame::PushPromise(promise) => promise.stream_id(),
+        }
+    }
+}
@@ -0,0 +1,435 @@
+// Capturing TLS in Java.
+//
+// JVMs do TLS in Java (JSSE) and not through a library that we can put probes on. So
+// when a process loads libjvm.so, we load a small Java agent into it (see java-agent/)
+// through HotSpot's attach mechanism. The agent wraps JSSE's sockets and engines and
+// sends us what goes through them, through a FIFO in the JVM's /tmp. We turn that into
+// TlsEvents, so to the event listener it looks like any other TLS library.
+//
+// This is all blocking code. Every JVM gets a thread of its own, which lives for as
+// long as the agent in the JVM sends us data.
+use futures::channel::mpsc::UnboundedSender;
+use probes::tls_mon::Kind;
+use probes::tls_mon::Library;
+use probes::tls_mon::TlsEvent;
+use probes::tls_mon::BUFSIZE;
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::mem;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixStream;
+use std::process;
+use std::slice;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+fn agent_jar() -> &'static [u8] {
+    include_bytes!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/target/java-agent/metrist-jsse-agent.jar"
+    ))
+}
+
+// What the agent is called in the JVM's /tmp.
+const AGENT_NAME: &str = "metrist-jsse-agent.jar";
+
+// A JVM needs to be up and running before it can be attached to.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+const ATTACH_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Connection ids from the agent become handles that can't clash with pointers.
+const JSSE_HANDLE: u64 = 1 << 63;
+
+// Kind, connection id, timestamp and length; the data follows.
+const HEADER_LEN: usize = 1 + 8 + 8 + 4;
+
+// The agent sends no more data than the probes capture.
+const MAX_RECORD_LEN: usize = HEADER_LEN + BUFSIZE;
+
+// From linux/openat2.h, which libc does not have for us.
+const RESOLVE_NO_SYMLINKS: u64 = 0x04;
+const RESOLVE_IN_ROOT: u64 = 0x10;
+
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+pub type EventSender = UnboundedSender<(String, Vec<Box<[u8]>>)>;
+
+// `pid` is the JVM's process id (tgid) as we see it.
+pub fn start_jvm_capture(pid: u32, events: EventSender) {
+    thread::spawn(move || match attach(pid) {
+        Ok(capture) => {
+            println!("Attached to JVM {}.", pid);
+            forward(pid, capture.fifo, &events);
+            unlink(&capture.tmp, &capture.fifo_name).ok();
+        }
+        Err(err) => println!("Cannot attach to JVM {}: {}", pid, err),
+    });
+}
+
+// The JVM's /tmp and our end of the FIFO in it.
+struct Capture {
+    tmp: File,
+    fifo_name: String,
+    fifo: File,
+}
+
+// Loads the agent, and returns the FIFO it reports through.
+//
+// Everything in the JVM's root filesystem is under control of whoever runs the JVM, so
+// we never follow symlinks there: /tmp gets resolved within the JVM's root, and we only
+// create files in it that did not exist yet.
+fn attach(pid: u32) -> Result<Capture, String> {
+    if !is_jvm(pid) {
+        return Err("not a JVM".to_string());
+    }
+
+    // HotSpot starts listening for attach requests on SIGQUIT. Until the JVM
+    // handles that signal, SIGQUIT would kill it.
+    let has_handler = wait_for(STARTUP_TIMEOUT, || {
+        status_field(pid, "SigCgt")
+            .and_then(|mask| u64::from_str_radix(&mask, 16).ok())
+            .map_or(false, |mask| mask & (1 << (libc::SIGQUIT - 1)) != 0)
+    });
+    if !has_handler {
+        return Err("no SIGQUIT handler, is attaching disabled?".to_string());
+    }
+
+    // In a container, the JVM knows itself by another pid, and the paths we use
+    // are relative to its root filesystem.
+    let ns_pid = status_field(pid, "NSpid")
+        .and_then(|pids| pids.split_ascii_whitespace().last().map(String::from))
+        .ok_or("process is gone")?;
+    let uid = status_id(pid, "Uid").ok_or("process is gone")?;
+    let gid = status_id(pid, "Gid").ok_or("process is gone")?;
+    let tmp = File::open(format!("/proc/{}/root", pid))
+        .and_then(|root| open_in_root(&root, "/tmp"))
+        .map_err(|err| format!("cannot open /tmp: {}", err))?;
+
+    // Other JVMs in the same container may be loading the jar, so we replace it
+    // rather than overwrite it.
+    let tmp_jar_name = format!("{}.{}", AGENT_NAME, ns_pid);
+    unlink(&tmp, &tmp_jar_name).ok();
+    create(&tmp, &tmp_jar_name, 0o644)
+        .and_then(|mut jar| jar.write_all(agent_jar()))
+        .and_then(|_| rename(&tmp, &tmp_jar_name, AGENT_NAME))
+        .map_err(|err| format!("cannot copy agent to /tmp/{}: {}", AGENT_NAME, err))?;
+
+    let fifo_name = format!(".metrist-jsse-{}-{}", ns_pid, process::id());
+    let fifo = make_fifo(&tmp, &fifo_name, uid, gid)
+        .map_err(|err| format!("cannot create /tmp/{}: {}", fifo_name, err))?;
+
+    let options = format!("/tmp/{}=/tmp/{}", AGENT_NAME, fifo_name);
+    if let Err(err) = load_agent(pid, &tmp, &ns_pid, uid, gid, &options) {
+        unlink(&tmp, &fifo_name).ok();
+        return Err(err);
+    }
+    Ok(Capture {
+        tmp,
+        fifo_name,
+        fifo,
+    })
+}
+
+// We get here when a process opens libjvm.so, but anything can open a file. Only
+// attach to processes that have it mapped.
+fn is_jvm(pid: u32) -> bool {
+    fs::read_to_string(format!("/proc/{}/maps", pid)).map_or(false, |maps| {
+        maps.lines().any(|line| line.ends_with("/libjvm.so"))
+    })
+}
+
+// HotSpot's attach mechanism. If the JVM does not have its attach socket yet, we create
+// a trigger file and send SIGQUIT. Then we ask it to load the agent jar.
+fn load_agent(
+    pid: u32,
+    tmp: &File,
+    ns_pid: &str,
+    uid: u32,
+    gid: u32,
+    options: &str,
+) -> Result<(), String> {
+    let socket_name = format!(".java_pid{}", ns_pid);
+    if open_socket(tmp, &socket_name).is_err() {
+        // The JVM looks in its working directory, then in /tmp.
+        let trigger_name = format!(".attach_pid{}", ns_pid);
+        let cwd = File::open(format!("/proc/{}/cwd", pid));
+        let trigger_dir = cwd
+            .iter()
+            .chain(Some(tmp))
+            .find(|dir| create_owned(dir, &trigger_name, uid, gid).is_ok())
+            .ok_or("cannot create attach trigger file")?;
+        unsafe {
+            libc::kill(pid as i32, libc::SIGQUIT);
+        }
+        let listening = wait_for(ATTACH_TIMEOUT, || open_socket(tmp, &socket_name).is_ok());
+        unlink(trigger_dir, &trigger_name).ok();
+        if !listening {
+            return Err("JVM did not start listening for attach requests".to_string());
+        }
+    }
+
+    // Connecting by path would follow symlinks, so we connect through the socket we
+    // found instead.
+    let mut stream = open_socket(tmp, &socket_name)
+        .and_then(|socket| connect_as(&socket, uid, gid))
+        .map_err(|err| format!("cannot connect to /tmp/{}: {}", socket_name, err))?;
+    let request = format!("1\0load\0instrument\0false\0{}\0", options);
+    let mut response = String::new();
+    stream
+        .write_all(request.as_bytes())
+        .and_then(|_| stream.read_to_string(&mut response))
+        .map_err(|err| format!("attach request failed: {}", err))?;
+
+    // A status line, followed by what the agent returned: "0" or "return code: 0",
+    // depending on the Java version.
+    let mut lines = response.lines();
+    let status = lines.next();
+    let agent_rc = lines.last().and_then(|line| line.rsplit(' ').next());
+    if status != Some("0") || agent_rc.map_or(false, |rc| rc != "0") {
+        return Err(format!("agent not loaded: {}", response.trim()));
+    }
+    Ok(())
+}
+
+// JVMs only take attach requests from their own user (recent ones also from root).
+// Unix sockets take the credentials of the thread that connects, and the raw system
+// calls only change those of the calling thread, unlike their libc wrappers which
+// change them for the whole process.
+fn connect_as(socket: &File, uid: u32, gid: u32) -> io::Result<UnixStream> {
+    let path = format!("/proc/self/fd/{}", socket.as_raw_fd());
+    let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+    unsafe {
+        libc::syscall(libc::SYS_setresgid, -1, gid, -1);
+        libc::syscall(libc::SYS_setresuid, -1, uid, -1);
+    }
+    let stream = UnixStream::connect(path);
+    unsafe {
+        libc::syscall(libc::SYS_setresuid, -1, euid, -1);
+        libc::syscall(libc::SYS_setresgid, -1, egid, -1);
+    }
+    stream
+}
+
+// The attach socket, if it is there.
+fn open_socket(dir: &File, name: &str) -> io::Result<File> {
+    let socket = open_at(dir, name, libc::O_PATH | libc::O_NOFOLLOW, 0)?;
+    if socket.metadata()?.file_type().is_socket() {
+        Ok(socket)
+    } else {
+        Err(io::Error::new(io::ErrorKind::Other, "not a socket"))
+    }
+}
+
+// Opens a directory in the JVM's root filesystem, as the JVM would see it.
+fn open_in_root(root: &File, path: &str) -> io::Result<File> {
+    let how = OpenHow {
+        flags: (libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC) as u64,
+        mode: 0,
+        resolve: RESOLVE_NO_SYMLINKS | RESOLVE_IN_ROOT,
+    };
+    let c_path = CString::new(path)?;
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            root.as_raw_fd(),
+            c_path.as_ptr(),
+            &how as *const OpenHow,
+            mem::size_of::<OpenHow>(),
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd as i32) })
+}
+
+fn create(dir: &File, name: &str, mode: libc::mode_t) -> io::Result<File> {
+    let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL | libc::O_NOFOLLOW;
+    open_at(dir, name, flags, mode)
+}
+
+fn create_owned(dir: &File, name: &str, uid: u32, gid: u32) -> io::Result<File> {
+    let file = create(dir, name, 0o600)?;
+    chown(&file, uid, gid)?;
+    Ok(file)
+}
+
+// Returns our end of the FIFO. We open it right away, so the agent does not have to
+// wait for us, and without blocking, so we don't have to wait for the agent.
+fn make_fifo(dir: &File, name: &str, uid: u32, gid: u32) -> io::Result<File> {
+    unlink(dir, name).ok();
+    let c_name = CString::new(name)?;
+    if unsafe { libc::mknodat(dir.as_raw_fd(), c_name.as_ptr(), libc::S_IFIFO | 0o600, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let flags = libc::O_RDONLY | libc::O_NONBLOCK | libc::O_NOFOLLOW;
+    let fifo = open_at(dir, name, flags, 0)?;
+    if !fifo.metadata()?.file_type().is_fifo() {
+        return Err(io::Error::new(io::ErrorKind::Other, "not a FIFO"));
+    }
+    chown(&fifo, uid, gid)?;
+    Ok(fifo)
+}
+
+fn open_at(dir: &File, name: &str, flags: i32, mode: libc::mode_t) -> io::Result<File> {
+    let c_name = CString::new(name)?;
+    let fd = unsafe {
+        libc::openat(
+            dir.as_raw_fd(),
+            c_name.as_ptr(),
+            flags | libc::O_CLOEXEC,
+            mode as libc::c_uint,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+fn chown(file: &File, uid: u32, gid: u32) -> io::Result<()> {
+    if unsafe { libc::fchown(file.as_raw_fd(), uid, gid) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn rename(dir: &File, from: &str, to: &str) -> io::Result<()> {
+    let (c_from, c_to) = (CString::new(from)?, CString::new(to)?);
+    let dir = dir.as_raw_fd();
+    if unsafe { libc::renameat(dir, c_from.as_ptr(), dir, c_to.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn unlink(dir: &File, name: &str) -> io::Result<()> {
+    let c_name = CString::new(name)?;
+    if unsafe { libc::unlinkat(dir.as_raw_fd(), c_name.as_ptr(), 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Reads what the agent sends until the JVM goes away.
+fn forward(pid: u32, fifo: File, events: &EventSender) {
+    // The agent says hello when it has opened its end. From then on we can block.
+    let mut poll_fd = libc::pollfd {
+        fd: fifo.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout_ms = ATTACH_TIMEOUT.as_millis() as i32;
+    if unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) } != 1 {
+        println!("Agent in JVM {} did not open its end of the FIFO.", pid);
+        return;
+    }
+    unsafe {
+        let flags = libc::fcntl(fifo.as_raw_fd(), libc::F_GETFL);
+        libc::fcntl(fifo.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK);
+    }
+
+    let mut reader = BufReader::new(fifo);
+    let mut len = [0u8; 4];
+    while reader.read_exact(&mut len).is_ok() {
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_RECORD_LEN {
+            println!("Bad record from JVM {}, {} bytes long.", pid, len);
+            break;
+        }
+        let mut record = vec![0u8; len];
+        if reader.read_exact(&mut record).is_err() {
+            break;
+        }
+        if let Some(event) = to_event(pid, &record) {
+            if events
+                .unbounded_send((String::from("jsse"), vec![event]))
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+}
+
+fn to_event(pid: u32, record: &[u8]) -> Option<Box<[u8]>> {
+    if record.len() < HEADER_LEN {
+        return None;
+    }
+    let kind = match record[0] {
+        1 => Kind::New,
+        2 => Kind::Write,
+        3 => Kind::Free,
+        4 => Kind::Read,
+        _ => return None,
+    };
+    let id = u64::from_be_bytes(record[1..9].try_into().ok()?);
+    let data = &record[HEADER_LEN..];
+    let captured = data.len().min(BUFSIZE);
+
+    let mut event = TlsEvent {
+        kind,
+        library: Library::Jsse,
+        pid,
+        tgid: pid,
+        ts: u64::from_be_bytes(record[9..17].try_into().ok()?),
+        handle: JSSE_HANDLE | (pid as u64) << 32 | (id & 0xFFFFFFFF),
+        len: u32::from_be_bytes(record[17..21].try_into().ok()?) as usize,
+        ..Default::default()
+    };
+    event.data[..captured].copy_from_slice(&data[..captured]);
+
+    let bytes = unsafe {
+        slice::from_raw_parts(
+            &event as *const TlsEvent as *const u8,
+            mem::size_of::<TlsEvent>(),
+        )
+    };
+    Some(bytes.into())
+}
+
+fn status_field(pid: u32, name: &str) -> Option<String> {
+    let status = fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+        .map(|value| value.trim().to_string())
+}
+
+// The effective id from "Uid" or "Gid": real, effective, saved, filesystem.
+fn status_id(pid: u32, name: &str) -> Option<u32> {
+    status_field(pid, name)?
+        .split_ascii_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+fn wait_for<F>(timeout: Duration, done: F) -> bool
+where
+    F: Fn() -> bool,
+{
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if done() {
+            return true;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+    false
+}
@@ -1,7 +1,13 @@
+use futures::channel::mpsc;
+use futures::future;
+use futures::stream;
 use redbpf::load::Loader;
 use rlimit::Resource;
 use std::env;
-use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::signal::unix::signal;
+use tokio::signal::unix::SignalKind;
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
 use uname::uname;
@@ -10,6 +16,31 @@ mod open_listener;
 use crate::open_listener::start_open_listener;
 mod event_listener;
 use crate::event_listener::start_event_listener;
+mod aggregate;
+mod container;
+use crate::container::start_containers;
+mod cri;
+mod dns;
+mod endpoint;
+mod executable;
+mod exporter;
+mod file_id;
+use crate::exporter::start_exporter;
+mod golang;
+mod grpc;
+mod http1;
+mod http2;
+mod jvm;
+mod path_template;
+mod plaintext;
+mod process;
+use crate::path_template::PathTemplater;
+use crate::process::Processes;
+mod record;
+use crate::record::Format;
+mod sink;
+use crate::sink::start_sink;
+use crate::sink::Endpoint;
 
 fn probe_code() -> &'static [u8] {
     include_bytes!(concat!(
@@ -40,13 +71,81 @@ async fn main() {
     }
 
     let loaded = Loader::load(probe_code()).expect("error on Loader::load");
-    let tx = start_open_listener(loaded.module);
+    let plaintext_ports = env::var("METRIST_PLAINTEXT_PORTS")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|port| !port.trim().is_empty())
+        .map(|port| port.trim().parse::<u16>().expect("Invalid plaintext port"))
+        .collect::<Vec<u16>>();
+    let plaintext_processes = env::var("METRIST_PLAINTEXT_PROCESSES")
+        .unwrap_or_default()
+        .split(',')
+        .map(|process| process.trim().to_string())
+        .filter(|process| !process.is_empty())
+        .collect::<Vec<String>>();
+    let plaintext = !plaintext_ports.is_empty() || !plaintext_processes.is_empty();
+    if plaintext {
+        plaintext::configure(&loaded, &plaintext_ports, &plaintext_processes);
+    }
+
+    let ktls = env::var("METRIST_KTLS_CAPTURE").unwrap_or("false".to_string()) == "true";
+
+    let (jvm_tx, jvm_rx) = mpsc::unbounded();
+    let tx = start_open_listener(loaded.module, jvm_tx, plaintext, ktls);
 
     let host = env::var("METRIST_ORCHESTRATOR_ENDPOINT").unwrap_or("127.0.0.1:51712".to_string());
-    let sock = UdpSocket::bind("0.0.0.0:0").expect("Could not bind socket");
-    sock.connect(host).expect("connect() call failed");
+    let endpoint = Endpoint::parse(&host).expect("Unknown Orchestrator endpoint type");
+    let queue_size = env::var("METRIST_ORCHESTRATOR_QUEUE_SIZE")
+        .unwrap_or("10000".to_string())
+        .parse::<usize>()
+        .expect("Invalid queue size");
+    let sink = start_sink(endpoint, queue_size);
+
+    let format_name = env::var("METRIST_RECORD_FORMAT").unwrap_or("tsv".to_string());
+    let format = Format::from_name(&format_name).expect("Unknown record format");
+    let window_secs = env::var("METRIST_AGGREGATION_WINDOW_SECS")
+        .unwrap_or("0".to_string())
+        .parse::<u64>()
+        .expect("Invalid aggregation window");
+    let window = if window_secs > 0 {
+        if format != Format::Json {
+            panic!("Aggregation needs the JSON record format.")
+        }
+        Some(Duration::from_secs(window_secs))
+    } else {
+        None
+    };
+    let keep_query = env::var("METRIST_KEEP_QUERY_STRING").unwrap_or("false".to_string()) == "true";
+    let mut templater = PathTemplater::new(keep_query);
+    if let Ok(file) = env::var("METRIST_PATH_RULES") {
+        templater.load_rules(&file).expect("Invalid path rules");
+    }
+    let exporter = start_exporter(sink, format, window, templater);
+
+    // On SIGTERM or SIGINT, send out what we have before exiting.
+    let shutdown_exporter = exporter.clone();
+    tokio::spawn(async move {
+        let mut sigterm = signal(SignalKind::terminate()).expect("could not handle SIGTERM");
+        let mut sigint = signal(SignalKind::interrupt()).expect("could not handle SIGINT");
+        future::select(Box::pin(sigterm.recv()), Box::pin(sigint.recv())).await;
+        println!("Shutting down.");
+        shutdown_exporter.shutdown().await;
+        std::process::exit(0);
+    });
+
+    let cri_socket = env::var("METRIST_CRI_SOCKET")
+        .ok()
+        .filter(|socket| !socket.is_empty())
+        .map(PathBuf::from);
+    let containers = start_containers(cri_socket);
+    let report_argv = env::var("METRIST_REPORT_ARGV").unwrap_or("false".to_string()) == "true";
+    let processes = Processes::new(report_argv);
+
+    // What our Java agents report comes in alongside what the probes report.
+    let events = stream::select(loaded.events, jvm_rx);
+    start_event_listener(events, exporter.clone(), tx, containers, processes).await;
 
-    start_event_listener(loaded.events, sock, tx).await;
+    exporter.shutdown().await;
 
     println!("Exiting.");
 }
@@ -1,53 +1,143 @@
 /**
  * We listen for open calls and try to deduce actual TLS library usage from there.
- * This runs from a separate thread that does a couple of things:
- * - It tracks process ids and their mount namespace
- * - It tracks mount namespaces and their root filesystems
- * - It tracks the top level filesystems
- * From these three items, it can quickly figure out what the "real" pathname in
- * a library open call is.
+ * This runs from a separate thread. The pathname in a library open call is as the
+ * process sees it, which in a container has little to do with where the file is on
+ * the host. We go through the process' root directory in /proc instead, which works
+ * whatever mount namespace, overlay filesystem or bind mount the library is in, and
+ * we recognize libraries by device and inode so we probe each file only once. Once
+ * the processes that use a library are gone, and so is the file, we stop probing it.
+ *
+ * Processes that were already running when we started don't open their libraries
+ * anymore, so at startup we go through what they have mapped instead.
  *
  * For now, we keep the probe etc simple by sending all messages through the
  * same channel from eBPF to user mode. This means that the event listener gets the
  * open messages, not this code; we setup a channel between the two to forward
  * these messages.
  */
+use crate::executable;
+use crate::executable::ExeProbes;
+use crate::file_id;
+use crate::file_id::{FileId, FileIds};
+use crate::jvm;
+use crate::jvm::EventSender;
+use crate::plaintext;
+use futures::future;
+use futures::future::Either;
 use redbpf::Module;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fs;
+use std::fs::File;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
+use std::sync::mpsc as std_mpsc;
+use std::thread;
 use std::time::Instant;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Receiver;
 use tokio::sync::mpsc::Sender;
 
+// Executables with nothing in them for us that we remember, so we don't look at them
+// every time they get started.
+const MAX_SEEN_EXES: usize = 4096;
+
+// Where distributions put the C library. We learn about other places from what running
+// processes have mapped.
+const LIBC_NAMES: [&str; 5] = [
+    "/lib/x86_64-linux-gnu/libc.so.6",
+    "/lib/aarch64-linux-gnu/libc.so.6",
+    "/lib64/libc.so.6",
+    "/usr/lib64/libc.so.6",
+    "/usr/lib/libc.so.6",
+];
+
 pub struct OpenMsg {
     pub lib_name: String,
     pub pid: u32,
+    pub tgid: u32,
+    // The process started a new executable; `lib_name` is empty.
+    pub is_exec: bool,
+}
+
+// An executable to look inside of, and what we found.
+struct Inspect {
+    id: FileId,
+    name: String,
+    file: File,
+}
+
+struct Inspected {
+    id: FileId,
+    name: String,
+    file: File,
+    result: Result<Option<ExeProbes>, String>,
 }
 
-pub fn start_open_listener(mut module: Module) -> Sender<OpenMsg> {
-    probe_kernel(&mut module);
+// Work that happens away from the runtime thread reports back through this.
+enum Work {
+    Inspected(Inspected),
+    // What a process that was running before we started has.
+    Running(OpenMsg),
+}
+
+// Events from the Java agents that we load into JVMs go to `jvm_events`.
+pub fn start_open_listener(
+    mut module: Module,
+    jvm_events: EventSender,
+    plaintext: bool,
+    ktls: bool,
+) -> Sender<OpenMsg> {
+    probe_kernel(&mut module, plaintext, ktls);
 
     // 1024 messages allows plenty of backlogs, which we'd expect if things
     // start up.
     let (tx, rx) = mpsc::channel::<OpenMsg>(1024);
     tokio::spawn(async move {
-        run_open_listener(rx, module).await;
+        run_open_listener(rx, module, jvm_events).await;
     });
     tx
 }
 
 #[allow(unused_must_use)]
-async fn run_open_listener(mut rx: Receiver<OpenMsg>, mut module: Module) {
-    let mut mount_ns_by_pid = HashMap::<u32, String>::new();
-    let mut root_by_ns = HashMap::<String, String>::new();
-    let mut system_mounts = HashMap::<String, String>::new();
-    let mut monitored_libs = HashSet::<String>::new();
+async fn run_open_listener(mut rx: Receiver<OpenMsg>, mut module: Module, jvm_events: EventSender) {
+    // Libraries we probe, by the file underneath; see file_id.
+    let mut monitored_libs = HashMap::<FileId, Lib>::new();
+    // Executables we looked at, by the file underneath: those we probe, and those with
+    // nothing in them for us, of which we only remember so many.
+    let mut probed_exes = HashSet::<FileId>::new();
+    let mut seen_exes = HashSet::<FileId>::new();
+    let mut file_ids = FileIds::new();
+    let (work_tx, mut work_rx) = mpsc::channel::<Work>(64);
+    start_scan(work_tx.clone());
+    let inspector = start_inspector(work_tx);
+    // Where processes have the C library, which the probes don't tell us about; see
+    // ignore() in the probes.
+    let mut libc_names = LIBC_NAMES
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<BTreeSet<String>>();
+    // JVMs that we loaded (or tried to load) our Java agent into.
+    let mut jvm_pids = HashSet::<u32>::new();
     let mut last_cleanup = Instant::now();
 
-    while let Some(cmd) = rx.recv().await {
+    loop {
+        // What we see happening goes first.
+        let cmd = match future::select(Box::pin(rx.recv()), Box::pin(work_rx.recv())).await {
+            Either::Left((Some(cmd), _)) => cmd,
+            Either::Right((Some(Work::Running(cmd)), _)) => cmd,
+            Either::Right((Some(Work::Inspected(inspected)), _)) => {
+                if let Ok(Some(_)) = inspected.result {
+                    seen_exes.remove(&inspected.id);
+                    probed_exes.insert(inspected.id);
+                }
+                attach_exe(&inspected, &mut module);
+                continue;
+            }
+            Either::Left((None, _)) | Either::Right((None, _)) => break,
+        };
         if last_cleanup.elapsed().as_secs() > 60 {
             // Every minute, we do a cleanup of our maps so we don't grow memory endlessly.
             // Cleanup code is inline to save us from having a function with a lot of arguments.
@@ -56,116 +146,174 @@ async fn run_open_listener(mut rx: Receiver<OpenMsg>, mut module: Module) {
             // we will get called, and if we are on a system that just starts a service and
             // that's it, we don't really need to be called.
 
-            // cleanout non existent pids
-            let mut pre_len = mount_ns_by_pid.len();
-            mount_ns_by_pid.retain(|&k, _| Path::new(format!("/proc/{}", k).as_str()).is_dir());
-            let mut post_len = mount_ns_by_pid.len();
-            println!(
-                "Cleanup: Cleaned {} pids, remaining {}, capacity {}",
-                pre_len - post_len,
-                post_len,
-                mount_ns_by_pid.capacity()
-            );
-
-            // cleanout unused namespaces. Note that the
-            // hashset is probably overkill, as machines typically
-            // don't have tons of active namespaces.
-            pre_len = root_by_ns.len();
-            let mut used_ns = HashSet::new();
-            for ns in mount_ns_by_pid.values() {
-                used_ns.insert(ns);
+            // A library stays probed while processes use it, or while it is still there
+            // for new processes to use. When it got replaced (a package upgrade) or went
+            // away with its container, and the last user exits, we detach from it. Users
+            // that we didn't see opening it (forked children, or processes in another
+            // container from the same image) keep it mapped, so we look at what processes
+            // have mapped before we do.
+            let mut unused = Vec::new();
+            for (id, lib) in monitored_libs.iter_mut() {
+                lib.users
+                    .retain(|&k| Path::new(format!("/proc/{}", k).as_str()).is_dir());
+                if lib.users.is_empty() && !lib.is_still_there(*id, &mut file_ids) {
+                    unused.push(*id);
+                }
+            }
+            if !unused.is_empty() {
+                let mapped = file_id::mapped_files();
+                unused.retain(|id| !mapped.contains(id));
+            }
+            for id in unused {
+                if let Some(lib) = monitored_libs.remove(&id) {
+                    unprobe_lib(&lib, &mut module);
+                }
             }
-            root_by_ns.retain(|k, _| used_ns.contains(k));
-            post_len = root_by_ns.len();
             println!(
-                "Cleanup: Cleaned {} namespaces, remaining {}, capacity {}",
-                pre_len - post_len,
-                post_len,
-                root_by_ns.capacity()
+                "Cleanup: monitored libs count is {}, capacity {}",
+                monitored_libs.len(),
+                monitored_libs.capacity()
             );
 
-            println!("Cleanup: monitored libs count is {}, capacity {}", monitored_libs.len(), monitored_libs.capacity());
-
-            // if we remove namespaces, we maybe also want to remove monitored libraries. What
-            // would be the rule for ending the monitoring of libraries?
+            jvm_pids.retain(|&k| Path::new(format!("/proc/{}", k).as_str()).is_dir());
 
             last_cleanup = Instant::now();
         }
 
-        // Options everywhere. While for an existing pid, all this stuff should exist, it may
-        // very well be the case that it exited before we get here.
-        let maybe_ns = match mount_ns_by_pid.get(&cmd.pid) {
-            Some(ns) => Some(ns),
-            None => {
-                if let Some(mount_ns) = get_mount_ns_by_pid(cmd.pid) {
-                    mount_ns_by_pid.insert(cmd.pid, mount_ns);
-                    mount_ns_by_pid.get(&cmd.pid)
-                } else {
-                    None
-                }
-            }
-        };
-
-        let maybe_root = if let Some(ns) = maybe_ns {
-            match root_by_ns.get(ns) {
-                Some(root) => Some(root),
-                None => {
-                    if let Some(root) = get_root_by_pid(cmd.pid) {
-                        root_by_ns.insert(ns.clone(), root);
-                        root_by_ns.get(ns)
-                    } else {
-                        None
-                    }
-                }
+        if cmd.is_exec {
+            inspect_exe(
+                cmd.pid,
+                &probed_exes,
+                &mut seen_exes,
+                &mut file_ids,
+                &inspector,
+            );
+            // The C library is there before the process gets to open it.
+            for lib_name in libc_names.iter() {
+                monitor_lib(
+                    &cmd,
+                    lib_name,
+                    &mut monitored_libs,
+                    &mut file_ids,
+                    &mut module,
+                );
             }
-        } else {
-            None
-        };
+            continue;
+        }
 
-        let maybe_real_root = if let Some(root) = maybe_root {
-            match system_mounts.get(root) {
-                Some(real) => Some(real),
-                None => {
-                    // Not found means we gotta refresh the map
-                    system_mounts = get_system_mounts();
-                    system_mounts.get(root)
-                }
+        if is_jvm(&cmd.lib_name) {
+            // Nothing to probe, Java does TLS in Java. We load our agent instead.
+            if jvm_pids.insert(cmd.tgid) {
+                jvm::start_jvm_capture(cmd.tgid, jvm_events.clone());
             }
-        } else {
-            None
-        };
+            continue;
+        }
 
-        // Combine library path and root path, check in watched libs
-        // If not available, start probing.
-        let maybe_real_path = if let Some(real_root) = maybe_real_root {
-            match real_root.as_str() {
-                "/" => Some(cmd.lib_name),
-                path => {
-                    let mut rp = String::from(path);
-                    rp.extend(cmd.lib_name.chars());
-                    Some(rp)
-                }
-            }
-        } else {
-            None
-        };
+        if is_libc(&cmd.lib_name) {
+            libc_names.insert(cmd.lib_name.clone());
+        }
+        monitor_lib(
+            &cmd,
+            &cmd.lib_name,
+            &mut monitored_libs,
+            &mut file_ids,
+            &mut module,
+        );
+    }
+}
 
-        if let Some(real_path) = maybe_real_path {
-            if monitored_libs.insert(real_path.clone()) {
-                // new entry, start monitoring
-                probe_lib(real_path.as_str(), &mut module);
-            }
+// Probes a library that a process opened, if we don't already.
+#[allow(unused_must_use)]
+fn monitor_lib(
+    cmd: &OpenMsg,
+    lib_name: &str,
+    monitored_libs: &mut HashMap<FileId, Lib>,
+    file_ids: &mut FileIds,
+    module: &mut Module,
+) {
+    // The process may very well have exited before we get here.
+    let path = lib_path(cmd.pid, lib_name);
+    let id = match File::open(&path).ok().and_then(|file| file_ids.id(&file)) {
+        Some(id) => id,
+        None => return,
+    };
+    // A library that got replaced, by a package upgrade for example, has a new
+    // inode, so it gets probed again. The same library in containers from the same
+    // image is the same file underneath, so it doesn't.
+    let lib = monitored_libs.entry(id).or_insert_with(|| {
+        // new entry, start monitoring
+        probe_lib(path.as_str(), module);
+        Lib {
+            host_path: host_path(cmd.pid, lib_name),
+            path,
+            users: HashSet::new(),
         }
+    });
+    lib.users.insert(cmd.tgid);
+}
+
+// A library that we probe.
+struct Lib {
+    // What we attached to, which is what we detach from.
+    path: String,
+    // Where the library is for processes outside of containers, if it is one of theirs.
+    host_path: Option<String>,
+    // The processes that we saw opening the library. Children that they fork use it
+    // too, without us knowing, but they mostly go together.
+    users: HashSet<u32>,
+}
+
+impl Lib {
+    // Whether new processes can still get at the library. Libraries in containers go
+    // away with the last process in there, as far as we're concerned.
+    fn is_still_there(&self, id: FileId, file_ids: &mut FileIds) -> bool {
+        self.host_path.as_ref().map_or(false, |host_path| {
+            File::open(host_path)
+                .ok()
+                .and_then(|file| file_ids.id(&file))
+                == Some(id)
+        })
+    }
+}
+
+// Where a library that a process opened is, as seen from here. Relative names are
+// relative to the working directory of the process.
+fn lib_path(pid: u32, lib_name: &str) -> String {
+    if lib_name.starts_with('/') {
+        format!("/proc/{}/root{}", pid, lib_name)
+    } else {
+        format!("/proc/{}/cwd/{}", pid, lib_name)
+    }
+}
+
+// The path of a library that a process opened, through the root of the host, if that
+// is the root of the process too. We go through the root of the first process instead
+// of our own, because we may run in a container of our own.
+fn host_path(pid: u32, lib_name: &str) -> Option<String> {
+    if !lib_name.starts_with('/') {
+        return None;
+    }
+    let root = fs::metadata(format!("/proc/{}/root", pid)).ok()?;
+    let host_root = fs::metadata("/proc/1/root").ok()?;
+    if root.dev() == host_root.dev() && root.ino() == host_root.ino() {
+        Some(format!("/proc/1/root{}", lib_name))
+    } else {
+        None
     }
 }
 
 fn probe_lib(lib: &str, module: &mut redbpf::Module) -> redbpf::Result<()> {
     println!("Attaching to {}.", lib);
-    // Note that this may fail - we have multiple library types and may insert the
-    // wrong probe for that library.
+    // Note that this may still fail, for example when a library was built without
+    // some of the functions we probe.
     for probe in module.uprobes_mut() {
-        let res = probe.attach_uprobe(Some(probe.name().as_str()), 0, lib, None);
-        if res.is_err() {
+        if !is_probe_for_lib(&probe.name(), lib) {
+            continue;
+        }
+        let name = probe.name();
+        let res = probe.attach_uprobe(Some(executable::probed_function(&name)), 0, lib, None);
+        // Only BoringSSL has this, OpenSSL has a macro by that name.
+        if res.is_err() && name != "SSL_set_tlsext_host_name" {
             println!(
                 "warning: could not attach uprobe {} to {}: {:?}",
                 probe.name(),
@@ -177,56 +325,284 @@ fn probe_lib(lib: &str, module: &mut redbpf::Module) -> redbpf::Result<()> {
     Ok(())
 }
 
-fn probe_kernel(module: &mut Module) {
-    // This should not fail, if it does, panicking is fine.
-    for probe in module.kprobes_mut() {
-        // As luck would have it, openat2() got introduced in the same kernel version
-        // as read_use_str() which pins the oldest kernel we can use. So we can safely
-        // assume it to be available.
-        probe
-            .attach_kprobe("do_sys_openat2", 0)
-            .expect("Cannot attach openat2 probe");
+fn unprobe_lib(lib: &Lib, module: &mut redbpf::Module) {
+    println!("Detaching from {}.", lib.path);
+    for probe in module.uprobes_mut() {
+        if !is_probe_for_lib(&probe.name(), &lib.path) {
+            continue;
+        }
+        if let Err(err) = probe.detach_uprobe(&lib.path) {
+            println!(
+                "warning: could not detach uprobe {} from {}: {:?}",
+                probe.name(),
+                lib.path,
+                err
+            );
+        }
     }
 }
 
-fn get_mount_ns_by_pid(pid: u32) -> Option<String> {
-    let file = format!("/proc/{}/ns/mnt", pid);
-    if let Ok(pb) = fs::read_link(file) {
-        let str = pb.to_str()?;
-        Some(String::from(str))
+// Each library only gets the probes for its own functions.
+fn is_probe_for_lib(probe: &str, lib: &str) -> bool {
+    let file_name = lib.rsplit('/').next().unwrap_or(lib);
+    if file_name.starts_with("libgnutls.so") {
+        probe.starts_with("gnutls_")
+    } else if file_name.starts_with("libnspr4.so") {
+        probe.starts_with("PR_")
+    } else if file_name.starts_with("libc.so") {
+        matches!(
+            executable::probed_function(probe),
+            "getaddrinfo" | "gethostbyname"
+        )
+    } else if file_name.starts_with("libssl3.so") {
+        // NSS, which has SSL_ functions of its own.
+        probe == "SSL_ImportFD"
     } else {
-        None
+        // libssl, and libnode which has OpenSSL linked in.
+        probe.starts_with("SSL_") && probe != "SSL_ImportFD"
+    }
+}
+
+// Processes that were running before we started have their libraries loaded already,
+// and the probes on exec and open will never tell us about them. So we look at what
+// they have mapped, and treat that as if we saw it happening. There can be a lot of
+// them, so we do this on a thread of its own, while we go on with what happens now.
+fn start_scan(work: Sender<Work>) {
+    thread::spawn(move || {
+        let (processes, mappings) =
+            scan_running_processes(|msg| work.blocking_send(Work::Running(msg)).is_ok());
+        println!(
+            "Found {} running processes, {} library mappings to look at.",
+            processes, mappings
+        );
+    });
+}
+
+// Returns how many processes and library mappings it sent, or stops when `send` fails.
+fn scan_running_processes<F>(mut send: F) -> (usize, usize)
+where
+    F: FnMut(OpenMsg) -> bool,
+{
+    let own_pid = std::process::id();
+    let pids = match fs::read_dir("/proc") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+            .filter(|pid| *pid != own_pid)
+            .collect::<Vec<u32>>(),
+        Err(err) => {
+            println!("Cannot look at running processes: {}", err);
+            return (0, 0);
+        }
+    };
+    let (mut processes, mut mappings) = (0, 0);
+    for pid in pids {
+        // Gone already, or a kernel thread.
+        let maps = match fs::read_to_string(format!("/proc/{}/maps", pid)) {
+            Ok(maps) if !maps.is_empty() => maps,
+            _ => continue,
+        };
+        processes += 1;
+        // The paths are as the process sees them, just like what it opens.
+        let libs = maps
+            .lines()
+            .filter_map(|line| {
+                // address perms offset dev inode path; deleted files have " (deleted)"
+                // after the path, and we skip those, and paths with spaces, here.
+                let fields = line.split_ascii_whitespace().collect::<Vec<&str>>();
+                match fields.as_slice() {
+                    [_, _, _, _, _, path] if is_wanted_lib(path) => Some(path.to_string()),
+                    _ => None,
+                }
+            })
+            .collect::<BTreeSet<String>>();
+        // A process that does TLS through a library has no TLS of its own, so there is
+        // no need to look inside its executable.
+        if libs.iter().all(|lib| is_libc(lib)) {
+            let exec = OpenMsg {
+                lib_name: String::from(""),
+                pid,
+                tgid: pid,
+                is_exec: true,
+            };
+            if !send(exec) {
+                return (processes, mappings);
+            }
+        }
+        for lib_name in libs {
+            mappings += 1;
+            let open = OpenMsg {
+                lib_name,
+                pid,
+                tgid: pid,
+                is_exec: false,
+            };
+            if !send(open) {
+                return (processes, mappings);
+            }
+        }
+    }
+    (processes, mappings)
+}
+
+// The libraries that the probe on open lets through (see ignore() in the probes), and
+// the C library.
+fn is_wanted_lib(path: &str) -> bool {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let is_versioned = |prefix: &str| {
+        file_name.strip_prefix(prefix).map_or(false, |version| {
+            !version.is_empty() && version.chars().all(|c| c.is_ascii_digit() || c == '.')
+        })
+    };
+    is_versioned("libssl.so.")
+        || is_versioned("libnode.so.")
+        || is_versioned("libgnutls.so.")
+        || matches!(
+            file_name,
+            "libnspr4.so" | "libssl3.so" | "libjvm.so" | "libc.so.6"
+        )
+}
+
+fn is_libc(lib: &str) -> bool {
+    lib.rsplit('/').next() == Some("libc.so.6")
+}
+
+fn is_jvm(lib: &str) -> bool {
+    lib.rsplit('/').next() == Some("libjvm.so")
+}
+
+// Executables don't get opened like libraries do, so for those we look at what a
+// process is running when it calls exec.
+fn inspect_exe(
+    pid: u32,
+    probed_exes: &HashSet<FileId>,
+    seen_exes: &mut HashSet<FileId>,
+    file_ids: &mut FileIds,
+    inspector: &std_mpsc::Sender<Inspect>,
+) {
+    // Going through /proc means that we don't have to care about which mount namespace
+    // the process is in, here or when attaching. We hold on to the file, so it doesn't
+    // matter when the process exits.
+    let exe = format!("/proc/{}/exe", pid);
+    let file = match File::open(&exe) {
+        Ok(file) => file,
+        // Gone already.
+        Err(_) => return,
+    };
+    let id = match file_ids.id(&file) {
+        Some(id) => id,
+        None => return,
+    };
+    if probed_exes.contains(&id) || seen_exes.contains(&id) {
+        return;
+    }
+    // Looking again at one that we forgot about is only a waste of time.
+    if seen_exes.len() >= MAX_SEEN_EXES {
+        seen_exes.clear();
     }
+    seen_exes.insert(id);
+    let name = fs::read_link(&exe).map_or(exe, |path| path.display().to_string());
+    inspector.send(Inspect { id, name, file }).ok();
 }
 
-fn get_root_by_pid(pid: u32) -> Option<String> {
-    let mounts = format!("/proc/{}/mounts", pid);
-    let contents = fs::read_to_string(mounts).ok()?;
-    // first line with " / "
-    let root_fs_line = contents
-        .lines()
-        .filter(|line| line.contains(" / "))
-        .next()
-        .unwrap();
-    let (key, _) = key_val_from_line(root_fs_line);
-    Some(key)
-}
-
-fn get_system_mounts() -> HashMap<String, String> {
-    // If we cannot open /proc/mounts, crashing is fine.
-    let contents = fs::read_to_string("/proc/mounts").expect("Could not read mounts file");
-    contents
-        .lines()
-        .map(|line| key_val_from_line(line))
-        .collect()
-}
-
-fn key_val_from_line(line: &str) -> (String, String) {
-    // Format is <device> <mount_point> <type> <opts> 0 0
-    let split: Vec<&str> = line.split_ascii_whitespace().collect();
-    let dev = String::from(split[0]);
-    let typ = String::from(split[2]);
-    let opt = String::from(split[3]);
-    let key = format!("{}:{}:{}", dev, typ, opt);
-    (key, String::from(split[1]))
+// Looking inside an executable means reading and parsing it, which takes a while for
+// big ones (Go binaries of 100MB are no exception). We do that on a thread of its own,
+// one at a time, so we keep up with what goes on meanwhile.
+fn start_inspector(work: Sender<Work>) -> std_mpsc::Sender<Inspect> {
+    let (tx, rx) = std_mpsc::channel::<Inspect>();
+    thread::spawn(move || {
+        for inspect in rx {
+            let result = executable::inspect(&inspect.file);
+            let inspected = Inspected {
+                id: inspect.id,
+                name: inspect.name,
+                file: inspect.file,
+                result,
+            };
+            if work.blocking_send(Work::Inspected(inspected)).is_err() {
+                break;
+            }
+        }
+    });
+    tx
+}
+
+fn attach_exe(inspected: &Inspected, module: &mut Module) {
+    match &inspected.result {
+        // The process may be gone, but we still have the file.
+        Ok(Some(exe_probes)) => probe_offsets(
+            &format!("/proc/self/fd/{}", inspected.file.as_raw_fd()),
+            &inspected.name,
+            exe_probes,
+            module,
+        ),
+        Ok(None) => {}
+        Err(err) => println!("Cannot probe executable {}: {}", inspected.name, err),
+    }
+}
+
+// `exe` is what we attach to, `exe_name` is what we call it in the log.
+fn probe_offsets(exe: &str, exe_name: &str, exe_probes: &ExeProbes, module: &mut Module) {
+    println!("Attaching to {} ({}).", exe_name, exe_probes.description);
+    for probe in module.uprobes_mut() {
+        let name = probe.name();
+        let offsets = match exe_probes
+            .attachments
+            .iter()
+            .find(|(probe, _)| *probe == name)
+        {
+            Some((_, offsets)) => offsets,
+            None => continue,
+        };
+        for offset in offsets {
+            let res = probe.attach_uprobe(None, *offset, exe, None);
+            if res.is_err() {
+                println!(
+                    "warning: could not attach uprobe {} to {} at {:#x}: {:?}",
+                    name, exe_name, offset, res
+                );
+            }
+        }
+    }
+}
+
+fn probe_kernel(module: &mut Module, plaintext: bool, ktls: bool) {
+    // This should not fail, if it does, panicking is fine.
+    for probe in module.kprobes_mut() {
+        let name = probe.name();
+        if plaintext::KTLS_PROBES.contains(&name.as_str()) {
+            if ktls {
+                if let Err(err) = probe.attach_kprobe(&name, 0) {
+                    println!(
+                        "warning: could not attach {} probe, is the tls module loaded? {:?}",
+                        name, err
+                    );
+                }
+            }
+            continue;
+        }
+        let is_cleanup = plaintext::CLEANUP_PROBES.contains(&name.as_str());
+        if !plaintext && !ktls && (is_cleanup || plaintext::PROBES.contains(&name.as_str())) {
+            continue;
+        }
+        if is_cleanup {
+            if let Err(err) = probe.attach_kprobe(&name, 0) {
+                println!("warning: could not attach {} probe: {:?}", name, err);
+            }
+            continue;
+        }
+        // Without IPv6, there is nothing to time IPv6 connects of.
+        if name == "tcp_v6_connect" {
+            if let Err(err) = probe.attach_kprobe(&name, 0) {
+                println!("warning: could not attach {} probe: {:?}", name, err);
+            }
+            continue;
+        }
+        // Probes are named after the kernel function they attach to. As luck would
+        // have it, openat2() got introduced in the same kernel version as
+        // read_use_str() which pins the oldest kernel we can use. So we can safely
+        // assume it to be available.
+        probe
+            .attach_kprobe(&name, 0)
+            .unwrap_or_else(|err| panic!("Cannot attach {} probe: {:?}", name, err));
+    }
 }
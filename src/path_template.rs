@@ -0,0 +1,273 @@
+// Turning request paths into route patterns.
+//
+// Paths like "/users/8812/orders/55" are different for every call, which makes them
+// useless for grouping in Orchestrator and leaks identifiers. Before we send a path
+// out, we replace the parts that look like identifiers with placeholders, giving
+// "/users/{id}/orders/{id}", and drop the query string.
+//
+// Not everything can be guessed, so on top of that you can supply rules for a host.
+// A rules file has a rule per line: a host, a regular expression and a replacement,
+// separated by whitespace. The host "*" matches all hosts and "*.example.com" all
+// subdomains of example.com. Empty lines and lines starting with '#' are ignored:
+//
+//   api.example.com  ^/v1/accounts/[^/]+  /v1/accounts/{account}
+//   *                ^/static/.*          /static/{file}
+//
+// Rules are applied in order before the built-in placeholders. The replacement can
+// refer to capture groups as "$1" etc.
+use regex::Regex;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+
+// Hex strings of at least this length are taken to be hashes or tokens.
+const MIN_HASH_LEN: usize = 16;
+
+struct Rule {
+    host: String,
+    regex: Regex,
+    replacement: String,
+}
+
+pub struct PathTemplater {
+    rules: Vec<Rule>,
+    keep_query: bool,
+}
+
+impl PathTemplater {
+    pub fn new(keep_query: bool) -> PathTemplater {
+        PathTemplater {
+            rules: Vec::new(),
+            keep_query,
+        }
+    }
+
+    // Load rules from a file, see above for the format.
+    pub fn load_rules(&mut self, file: &str) -> Result<(), String> {
+        let contents = fs::read_to_string(file).map_err(|err| format!("{}: {}", file, err))?;
+        for (idx, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 {
+                return Err(format!(
+                    "{}:{}: expected host, regex and replacement",
+                    file,
+                    idx + 1
+                ));
+            }
+            let regex =
+                Regex::new(fields[1]).map_err(|err| format!("{}:{}: {}", file, idx + 1, err))?;
+            self.rules.push(Rule {
+                host: fields[0].to_ascii_lowercase(),
+                regex,
+                replacement: fields[2].to_string(),
+            });
+        }
+        println!("Loaded {} path rules from {}", self.rules.len(), file);
+        Ok(())
+    }
+
+    pub fn template(&self, host: &str, url: &str) -> String {
+        let (path, query) = match url.find('?') {
+            Some(idx) => (&url[..idx], &url[idx..]),
+            None => (url, ""),
+        };
+
+        let host = strip_port(host).to_ascii_lowercase();
+        let mut path = path.to_string();
+        for rule in self
+            .rules
+            .iter()
+            .filter(|rule| host_matches(&rule.host, &host))
+        {
+            path = rule
+                .regex
+                .replace_all(&path, rule.replacement.as_str())
+                .into_owned();
+        }
+
+        let mut templated = path
+            .split('/')
+            .map(|segment| placeholder(segment).unwrap_or(segment))
+            .collect::<Vec<&str>>()
+            .join("/");
+        if self.keep_query {
+            templated.push_str(query);
+        }
+        templated
+    }
+}
+
+fn strip_port(host: &str) -> &str {
+    // "[::1]:8443" or "example.com:8443"; a bare IPv6 address has more than one colon.
+    if let Some(rest) = host.strip_prefix('[') {
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match host.rfind(':') {
+        Some(idx) if host.matches(':').count() == 1 => &host[..idx],
+        _ => host,
+    }
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.strip_prefix("*.") {
+        Some(domain) => {
+            host.ends_with(domain)
+                && host.len() > domain.len()
+                && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+        }
+        None => pattern == host,
+    }
+}
+
+// The placeholder for a path segment that looks like an identifier.
+fn placeholder(segment: &str) -> Option<&'static str> {
+    if segment.is_empty() || segment.starts_with('{') {
+        return None;
+    }
+    if segment.bytes().all(|b| b.is_ascii_digit()) {
+        Some("{id}")
+    } else if is_uuid(segment) {
+        Some("{uuid}")
+    } else if segment.parse::<Ipv4Addr>().is_ok() || segment.parse::<Ipv6Addr>().is_ok() {
+        Some("{ip}")
+    } else if is_email(segment) {
+        Some("{email}")
+    } else if is_hash(segment) {
+        Some("{hash}")
+    } else {
+        None
+    }
+}
+
+// 8-4-4-4-12 hex digits.
+fn is_uuid(segment: &str) -> bool {
+    let groups: Vec<&str> = segment.split('-').collect();
+    groups.len() == 5
+        && groups
+            .iter()
+            .zip([8, 4, 4, 4, 12].iter())
+            .all(|(group, len)| group.len() == *len && is_hex(group))
+}
+
+fn is_hash(segment: &str) -> bool {
+    segment.len() >= MIN_HASH_LEN && is_hex(segment) && segment.bytes().any(|b| b.is_ascii_digit())
+}
+
+fn is_hex(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn is_email(segment: &str) -> bool {
+    let segment = segment.replace("%40", "@");
+    match segment.split_once('@') {
+        Some((user, domain)) => !user.is_empty() && domain.contains('.') && !domain.ends_with('.'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_identifiers() {
+        let templater = PathTemplater::new(false);
+        let cases = [
+            ("/users/8812/orders/55", "/users/{id}/orders/{id}"),
+            (
+                "/items/123e4567-e89b-12d3-a456-426614174000",
+                "/items/{uuid}",
+            ),
+            ("/hosts/10.0.0.1/status", "/hosts/{ip}/status"),
+            ("/hosts/fe80::1", "/hosts/{ip}"),
+            ("/users/jane%40example.com", "/users/{email}"),
+            ("/blobs/9f86d081884c7d659a2feaa0c55ad015", "/blobs/{hash}"),
+            // Hex, but it could just as well be a word.
+            ("/feed/deadbeefdeadbeef", "/feed/deadbeefdeadbeef"),
+            ("/v1/users/me", "/v1/users/me"),
+            ("/", "/"),
+            ("", ""),
+        ];
+        for (path, expected) in cases.iter() {
+            assert_eq!(templater.template("example.com", path), *expected);
+        }
+    }
+
+    #[test]
+    fn query_strings() {
+        let url = "/search/42?q=secret&page=2";
+        assert_eq!(PathTemplater::new(false).template("a", url), "/search/{id}");
+        assert_eq!(
+            PathTemplater::new(true).template("a", url),
+            "/search/{id}?q=secret&page=2"
+        );
+    }
+
+    #[test]
+    fn rules_per_host() {
+        let file = std::env::temp_dir().join(format!("path-rules-{}", std::process::id()));
+        fs::write(
+            &file,
+            "# accounts have names\n\
+             \n\
+             api.example.com  ^/v1/accounts/[^/]+  /v1/accounts/{account}\n\
+             *.example.org    ^/files/(\\w+)/.*    /files/$1/{file}\n",
+        )
+        .unwrap();
+        let mut templater = PathTemplater::new(false);
+        templater.load_rules(file.to_str().unwrap()).unwrap();
+        fs::remove_file(&file).unwrap();
+
+        assert_eq!(
+            templater.template("API.example.com:443", "/v1/accounts/acme/users/7"),
+            "/v1/accounts/{account}/users/{id}"
+        );
+        assert_eq!(
+            templater.template("other.example.com", "/v1/accounts/acme"),
+            "/v1/accounts/acme"
+        );
+        assert_eq!(
+            templater.template("cdn.example.org", "/files/img/a/b.png"),
+            "/files/img/{file}"
+        );
+        // "*.example.org" is for subdomains only.
+        assert_eq!(
+            templater.template("example.org", "/files/img/a.png"),
+            "/files/img/a.png"
+        );
+        assert_eq!(
+            templater.template("badexample.org", "/files/img/a.png"),
+            "/files/img/a.png"
+        );
+    }
+
+    #[test]
+    fn bad_rules() {
+        let file = std::env::temp_dir().join(format!("bad-path-rules-{}", std::process::id()));
+        fs::write(&file, "* ^/a\n").unwrap();
+        let err = PathTemplater::new(false)
+            .load_rules(file.to_str().unwrap())
+            .unwrap_err();
+        fs::remove_file(&file).unwrap();
+        assert!(
+            err.ends_with(":1: expected host, regex and replacement"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn ports() {
+        assert_eq!(strip_port("example.com:8443"), "example.com");
+        assert_eq!(strip_port("[::1]:8443"), "::1");
+        assert_eq!(strip_port("::1"), "::1");
+        assert_eq!(strip_port("example.com"), "example.com");
+    }
+}
@@ -0,0 +1,69 @@
+// Plaintext HTTP capture, for traffic that does not use TLS at all. The probes (see
+// probes/src/tls_mon/socket.rs) look at TCP sockets to the remote ports, or in the
+// processes, that we put in their maps here. They sit on hot paths in the kernel, so
+// they are only attached if something is configured.
+//
+// Sockets that do TLS in the kernel (kTLS) get their plaintext picked up by the same
+// probes, with a couple of extra ones to recognize them.
+use redbpf::load::Loaded;
+use redbpf::HashMap;
+
+// tcp_close is not in here, it also tells us when connected sockets go away.
+pub const PROBES: [&str; 6] = [
+    "tcp_sendmsg",
+    "tcp_recvmsg",
+    "vfs_write",
+    "vfs_read",
+    "__sys_sendto",
+    "__sys_recvfrom",
+];
+
+// These only clean up after system calls that we don't capture. Some of them are
+// static functions that the compiler may have inlined, so they are optional.
+pub const CLEANUP_PROBES: [&str; 5] = [
+    "do_writev",
+    "do_readv",
+    "__sys_sendmsg",
+    "__sys_recvmsg",
+    "do_sendfile",
+];
+
+// These are in the tls kernel module, which may not be loaded.
+pub const KTLS_PROBES: [&str; 2] = ["tls_sw_sendmsg", "tls_sw_recvmsg"];
+
+// The kernel keeps up to 15 bytes of a process name.
+const COMM_LEN: usize = 16;
+
+pub fn configure(loaded: &Loaded, ports: &[u16], processes: &[String]) {
+    // The maps are in the probe code, so not finding them is a bug.
+    let port_map = HashMap::<u16, u8>::new(
+        loaded
+            .map("PLAIN_PORTS")
+            .expect("PLAIN_PORTS map not found"),
+    )
+    .expect("Cannot use PLAIN_PORTS map");
+    for port in ports {
+        port_map.set(*port, 1);
+    }
+    let process_map = HashMap::<[u8; COMM_LEN], u8>::new(
+        loaded
+            .map("PLAIN_PROCESSES")
+            .expect("PLAIN_PROCESSES map not found"),
+    )
+    .expect("Cannot use PLAIN_PROCESSES map");
+    for process in processes {
+        process_map.set(comm(process), 1);
+    }
+    println!(
+        "Capturing plaintext HTTP to ports {:?} and in processes {:?}.",
+        ports, processes
+    );
+}
+
+// A process name as the kernel has it: truncated, and padded with zeroes.
+fn comm(name: &str) -> [u8; COMM_LEN] {
+    let mut comm = [0u8; COMM_LEN];
+    let len = name.len().min(COMM_LEN - 1);
+    comm[..len].copy_from_slice(&name.as_bytes()[..len]);
+    comm
+}
@@ -0,0 +1,211 @@
+// Who a process is: its executable, command line, user and service.
+//
+// We look this up in /proc the first time we see a process and keep it until the
+// process is gone, so events that come in after a process exited still get reported
+// with it. Pids get reused, so we also keep the start time of the process and look
+// again when a pid turns out to belong to a new one.
+//
+// Command lines often have passwords and tokens in them, so we only report those when
+// asked to.
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+// Command lines can be huge (think Java class paths), and they go into every record.
+const MAX_ARGV_LEN: usize = 4096;
+
+pub struct Process {
+    pub comm: String,
+    pub exe: String,
+    // Empty unless we report command lines.
+    pub argv: Vec<String>,
+    // The real uid.
+    pub uid: Option<u32>,
+    // The systemd unit that the process runs in, like "nginx.service".
+    pub unit: String,
+    // In clock ticks since boot, as the kernel reports it.
+    start_ticks: u64,
+    // Milliseconds since the Unix epoch, or 0 if we don't know.
+    pub start_ms: u64,
+}
+
+pub struct Processes {
+    by_tgid: HashMap<u32, Arc<Process>>,
+    with_argv: bool,
+    boot_ms: u64,
+    ticks_per_sec: u64,
+}
+
+impl Processes {
+    pub fn new(with_argv: bool) -> Processes {
+        let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        Processes {
+            by_tgid: HashMap::new(),
+            with_argv,
+            boot_ms: boot_time_ms().unwrap_or(0),
+            ticks_per_sec: if ticks_per_sec > 0 {
+                ticks_per_sec as u64
+            } else {
+                100
+            },
+        }
+    }
+
+    // None if we never saw the process and it is gone by now.
+    pub fn get(&mut self, tgid: u32) -> Option<Arc<Process>> {
+        let start_ticks = start_ticks(tgid);
+        if let Some(process) = self.by_tgid.get(&tgid) {
+            // Gone processes stay around until the next cleanup.
+            if start_ticks.is_none() || start_ticks == Some(process.start_ticks) {
+                return Some(process.clone());
+            }
+        }
+        let process = Arc::new(self.read(tgid, start_ticks?));
+        self.by_tgid.insert(tgid, process.clone());
+        Some(process)
+    }
+
+    // Forget about processes that are gone, or whose pid belongs to another one now.
+    pub fn cleanup(&mut self) {
+        self.by_tgid
+            .retain(|tgid, process| start_ticks(*tgid) == Some(process.start_ticks));
+    }
+
+    fn read(&self, tgid: u32, start_ticks: u64) -> Process {
+        let proc_dir = format!("/proc/{}", tgid);
+        let comm = fs::read_to_string(format!("{}/comm", proc_dir))
+            .map(|comm| comm.trim_end().to_string())
+            .unwrap_or_default();
+        // Kernel threads and zombies have no executable.
+        let exe = fs::read_link(format!("{}/exe", proc_dir))
+            .map(|exe| exe.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let argv = if self.with_argv {
+            fs::read(format!("{}/cmdline", proc_dir))
+                .map(|cmdline| parse_cmdline(&cmdline))
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        let uid = fs::read_to_string(format!("{}/status", proc_dir))
+            .ok()
+            .and_then(|status| parse_uid(&status));
+        let unit = fs::read_to_string(format!("{}/cgroup", proc_dir))
+            .ok()
+            .and_then(|cgroups| systemd_unit(&cgroups))
+            .unwrap_or_default();
+        let start_ms = if self.boot_ms == 0 {
+            0
+        } else {
+            self.boot_ms + start_ticks * 1000 / self.ticks_per_sec
+        };
+        Process {
+            comm,
+            exe,
+            argv,
+            uid,
+            unit,
+            start_ticks,
+            start_ms,
+        }
+    }
+}
+
+// The 22nd field of /proc/<pid>/stat. The second field is the command in parentheses,
+// which can have anything in it, so we count from the last parenthesis.
+fn start_ticks(tgid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", tgid)).ok()?;
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_ascii_whitespace().nth(19)?.parse().ok()
+}
+
+// The "btime" line of /proc/stat, in seconds since the Unix epoch.
+fn boot_time_ms() -> Option<u64> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    let btime = stat.lines().find_map(|line| line.strip_prefix("btime "))?;
+    btime.trim().parse::<u64>().ok().map(|secs| secs * 1000)
+}
+
+// Arguments are separated, and ended, by null bytes.
+fn parse_cmdline(cmdline: &[u8]) -> Vec<String> {
+    let cmdline = &cmdline[0..cmdline.len().min(MAX_ARGV_LEN)];
+    let cmdline = cmdline.strip_suffix(&[0]).unwrap_or(cmdline);
+    if cmdline.is_empty() {
+        return Vec::new();
+    }
+    cmdline
+        .split(|b| *b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).to_string())
+        .collect()
+}
+
+// "Uid:\t<real>\t<effective>\t<saved>\t<filesystem>"
+fn parse_uid(status: &str) -> Option<u32> {
+    let uids = status.lines().find_map(|line| line.strip_prefix("Uid:"))?;
+    uids.split_ascii_whitespace().next()?.parse().ok()
+}
+
+// The innermost service in the cgroup path, like "/system.slice/nginx.service", or
+// "/user.slice/user-1000.slice/user@1000.service/app.slice/foo.service" for user units.
+fn systemd_unit(cgroups: &str) -> Option<String> {
+    cgroups.lines().find_map(|line| {
+        let path = line.splitn(3, ':').nth(2)?;
+        Path::new(path)
+            .iter()
+            .filter_map(|part| part.to_str())
+            .filter(|part| part.ends_with(".service"))
+            .last()
+            .map(String::from)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn systemd_units() {
+        assert_eq!(
+            systemd_unit("0::/system.slice/nginx.service\n").as_deref(),
+            Some("nginx.service")
+        );
+        assert_eq!(
+            systemd_unit(
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/foo.service\n"
+            )
+            .as_deref(),
+            Some("foo.service")
+        );
+        let v1 = "12:pids:/system.slice/ssh.service\n1:name=systemd:/system.slice/ssh.service\n";
+        assert_eq!(systemd_unit(v1).as_deref(), Some("ssh.service"));
+        assert_eq!(
+            systemd_unit("0::/user.slice/user-1000.slice/session-2.scope\n"),
+            None
+        );
+        assert_eq!(systemd_unit("0::/\n"), None);
+        assert_eq!(systemd_unit(""), None);
+    }
+
+    #[test]
+    fn cmdlines() {
+        assert_eq!(
+            parse_cmdline(b"nginx\0-g\0daemon off;\0"),
+            vec!["nginx", "-g", "daemon off;"]
+        );
+        // Processes can rewrite theirs, and leave out the last null byte.
+        assert_eq!(parse_cmdline(b"postgres: writer"), vec!["postgres: writer"]);
+        assert_eq!(parse_cmdline(b"a\0\0b\0"), vec!["a", "", "b"]);
+        assert!(parse_cmdline(b"").is_empty());
+        assert!(parse_cmdline(b"\0").is_empty());
+        let long = vec![b'x'; MAX_ARGV_LEN + 100];
+        assert_eq!(parse_cmdline(&long)[0].len(), MAX_ARGV_LEN);
+    }
+
+    #[test]
+    fn uids() {
+        let status = "Name:\tcat\nUid:\t1000\t0\t0\t0\nGid:\t100\t100\t100\t100\n";
+        assert_eq!(parse_uid(status), Some(1000));
+        assert_eq!(parse_uid("Name:\tcat\n"), None);
+    }
+}
@@ -0,0 +1,266 @@
+// The records we send to Orchestrator.
+//
+// The original format is a tab separated line with a magic leading "0" and no escaping
+// whatsoever. It is still the default, so older Orchestrator versions keep working.
+// With METRIST_RECORD_FORMAT=json we send a JSON object per line instead, which carries
+// a version number so it can evolve.
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+
+// Bump this when fields change meaning or disappear. Adding fields is fine.
+pub const RECORD_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    // "0\t{method}\t{host}\t{url}\t{ms}\n"
+    LegacyTsv,
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Some(Format::Json),
+            "tsv" | "legacy" => Some(Format::LegacyTsv),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Record<'a> {
+    pub version: u32,
+    // "transaction" for these, "aggregate" for an AggregateRecord.
+    pub kind: &'a str,
+    // Milliseconds since the Unix epoch at which the transaction ended.
+    pub time: u64,
+    pub protocol: &'a str,
+    pub tls_library: &'a str,
+    pub pid: u32,
+    pub process: &'a str,
+    #[serde(flatten)]
+    pub process_info: Option<ProcessInfo<'a>>,
+    pub method: &'a str,
+    pub host: &'a str,
+    pub path: &'a str,
+    pub status: u16,
+    // From the request being written to the last of the response.
+    pub duration_ms: f64,
+    // Setting up the connection, for the first transaction on it: the TCP connect (not
+    // counting DNS) and the TLS handshake.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_ms: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake_ms: Option<f64>,
+    // From the request being written to the first of the response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttfb_ms: Option<f64>,
+    // The duration plus whatever we saw of setting up the connection.
+    pub total_ms: f64,
+    pub request_bytes: usize,
+    pub response_bytes: usize,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub content_type: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grpc: Option<Grpc<'a>>,
+    // Set when we could not decode the request or response headers.
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub decode_error: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container: Option<Container<'a>>,
+    // Where the connection went, if we saw it being set up.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_port: Option<u16>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub sni: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct Grpc<'a> {
+    pub service: &'a str,
+    pub method: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub status: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub message: &'a str,
+}
+
+// More about the process than its name, see process.rs. Missing if the process was
+// gone before we got to look at it.
+#[derive(Serialize)]
+pub struct ProcessInfo<'a> {
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub exe: &'a str,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    pub argv: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub unit: &'a str,
+    // Milliseconds since the Unix epoch, which tells processes with the same pid apart.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub process_start_time: Option<u64>,
+}
+
+// Where the process runs, see container.rs. Processes that don't run in a container
+// only have a cgroup.
+#[derive(Serialize)]
+pub struct Container<'a> {
+    pub cgroup: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub id: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub runtime: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pod: Option<Pod<'a>>,
+}
+
+// A Kubernetes pod. Without access to the container runtime, we only know its uid.
+#[derive(Serialize)]
+pub struct Pod<'a> {
+    pub uid: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub name: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    pub namespace: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<&'a BTreeMap<String, String>>,
+}
+
+// A DNS lookup, see dns.rs. These only exist in JSON.
+#[derive(Serialize)]
+pub struct DnsRecord<'a> {
+    pub version: u32,
+    // "dns"
+    pub kind: &'a str,
+    // Milliseconds since the Unix epoch at which the lookup ended.
+    pub time: u64,
+    pub pid: u32,
+    pub process: &'a str,
+    #[serde(flatten)]
+    pub process_info: Option<ProcessInfo<'a>>,
+    pub name: &'a str,
+    // "ok", or the EAI_* error.
+    pub status: &'a str,
+    pub duration_ms: f64,
+    pub addresses: Vec<String>,
+}
+
+// Transactions grouped over a window, see aggregate.rs. These only exist in JSON.
+#[derive(Serialize)]
+pub struct AggregateRecord<'a> {
+    pub version: u32,
+    pub kind: &'a str,
+    // Milliseconds since the Unix epoch at which the window ended.
+    pub time: u64,
+    pub window_ms: u64,
+    pub method: &'a str,
+    pub host: &'a str,
+    pub path: &'a str,
+    pub status: u16,
+    pub count: u64,
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+    pub duration_ms: Latency,
+}
+
+// DNS lookups grouped over a window, by name and status.
+#[derive(Serialize)]
+pub struct DnsAggregateRecord<'a> {
+    pub version: u32,
+    // "dns_aggregate"
+    pub kind: &'a str,
+    // Milliseconds since the Unix epoch at which the window ended.
+    pub time: u64,
+    pub window_ms: u64,
+    pub name: &'a str,
+    pub status: &'a str,
+    pub count: u64,
+    pub duration_ms: Latency,
+}
+
+#[derive(Serialize)]
+pub struct Latency {
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+impl<'a> Record<'a> {
+    pub fn encode(&self, format: Format) -> String {
+        match format {
+            Format::Json => {
+                // Serializing a struct of strings and numbers cannot fail.
+                let mut line = serde_json::to_string(self).unwrap();
+                line.push('\n');
+                line
+            }
+            Format::LegacyTsv => format!(
+                "0\t{}\t{}\t{}\t{}\n",
+                tsv_field(self.method),
+                tsv_field(self.host),
+                tsv_field(self.path),
+                self.duration_ms as f32
+            ),
+        }
+    }
+}
+
+impl<'a> DnsRecord<'a> {
+    pub fn encode(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap();
+        line.push('\n');
+        line
+    }
+}
+
+impl<'a> AggregateRecord<'a> {
+    pub fn encode(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap();
+        line.push('\n');
+        line
+    }
+}
+
+impl<'a> DnsAggregateRecord<'a> {
+    pub fn encode(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap();
+        line.push('\n');
+        line
+    }
+}
+
+// The legacy format has no escaping, so the best we can do is to make sure that
+// a field cannot break the record.
+fn tsv_field(field: &str) -> String {
+    field.replace(|c| c == '\t' || c == '\n' || c == '\r', " ")
+}
+
+// Event timestamps are nanoseconds on the monotonic clock, so we go by how long ago
+// they were.
+pub fn ktime_to_ms(ns: u64) -> u64 {
+    now_ms().saturating_sub(ktime_now_ns().saturating_sub(ns) / 1_000_000)
+}
+
+// Now, on the clock of event timestamps.
+pub fn ktime_now_ns() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
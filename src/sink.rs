@@ -0,0 +1,191 @@
+// Delivery of encoded records to Orchestrator.
+//
+// Records are handed to a separate task through a bounded channel, which doubles as
+// the queue we hold records in while Orchestrator is unreachable. The task owns the
+// connection and reconnects with exponential backoff. If the queue is full, records
+// are dropped and counted, we never block event processing on the network.
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::net::UdpSocket;
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::Sender;
+
+const MIN_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const DRAIN_POLL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Udp(String),
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl Endpoint {
+    // "udp://host:port", "tcp://host:port" or "unix:///path/to/socket". A plain
+    // "host:port" is UDP, which is what we always used.
+    pub fn parse(endpoint: &str) -> Option<Endpoint> {
+        if let Some(addr) = endpoint.strip_prefix("udp://") {
+            Some(Endpoint::Udp(addr.to_string()))
+        } else if let Some(addr) = endpoint.strip_prefix("tcp://") {
+            Some(Endpoint::Tcp(addr.to_string()))
+        } else if let Some(path) = endpoint.strip_prefix("unix://") {
+            Some(Endpoint::Unix(PathBuf::from(path)))
+        } else if endpoint.contains("://") {
+            None
+        } else {
+            Some(Endpoint::Udp(endpoint.to_string()))
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SinkStats {
+    pub sent: AtomicU64,
+    pub dropped: AtomicU64,
+    pub reconnects: AtomicU64,
+    // Records in the queue or being sent.
+    pub pending: AtomicU64,
+}
+
+pub struct Sink {
+    tx: Sender<Vec<u8>>,
+    stats: Arc<SinkStats>,
+}
+
+impl Sink {
+    // Queue a record for sending.
+    pub fn send(&self, msg: Vec<u8>) {
+        // Counted up front, so the sink task never sees it before we do.
+        self.stats.pending.fetch_add(1, Ordering::Relaxed);
+        match self.tx.try_send(msg) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Closed(_)) => {
+                self.stats.pending.fetch_sub(1, Ordering::Relaxed);
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn log_stats(&self) {
+        println!(
+            "Sink: sent {}, dropped {}, reconnects {}, queued {}",
+            self.stats.sent.load(Ordering::Relaxed),
+            self.stats.dropped.load(Ordering::Relaxed),
+            self.stats.reconnects.load(Ordering::Relaxed),
+            self.stats.pending.load(Ordering::Relaxed)
+        );
+    }
+
+    // Wait for everything that is queued to be sent, for at most `timeout`.
+    pub async fn drain(&self, timeout: Duration) {
+        let start = Instant::now();
+        while self.stats.pending.load(Ordering::Relaxed) > 0 && start.elapsed() < timeout {
+            tokio::time::sleep(DRAIN_POLL).await;
+        }
+        self.log_stats();
+    }
+}
+
+pub fn start_sink(endpoint: Endpoint, queue_size: usize) -> Sink {
+    let (tx, rx) = mpsc::channel::<Vec<u8>>(queue_size.max(1));
+    let stats = Arc::new(SinkStats::default());
+    let task_stats = stats.clone();
+    tokio::spawn(async move {
+        run_sink(endpoint, rx, task_stats).await;
+    });
+    Sink { tx, stats }
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Connection {
+    async fn connect(endpoint: &Endpoint) -> io::Result<Connection> {
+        match endpoint {
+            Endpoint::Udp(addr) => {
+                let sock = UdpSocket::bind("0.0.0.0:0").await?;
+                sock.connect(addr).await?;
+                Ok(Connection::Udp(sock))
+            }
+            Endpoint::Tcp(addr) => Ok(Connection::Tcp(TcpStream::connect(addr).await?)),
+            Endpoint::Unix(path) => Ok(Connection::Unix(UnixStream::connect(path).await?)),
+        }
+    }
+
+    async fn send(&mut self, msg: &[u8]) -> io::Result<()> {
+        match self {
+            Connection::Udp(sock) => sock.send(msg).await.map(|_| ()),
+            Connection::Tcp(stream) => stream.write_all(msg).await,
+            Connection::Unix(stream) => stream.write_all(msg).await,
+        }
+    }
+}
+
+async fn run_sink(endpoint: Endpoint, mut rx: Receiver<Vec<u8>>, stats: Arc<SinkStats>) {
+    let mut backoff = MIN_BACKOFF;
+    // A record we could not send on a broken connection, to be sent first on the next one.
+    let mut retry: Option<Vec<u8>> = None;
+    loop {
+        let mut conn = match Connection::connect(&endpoint).await {
+            Ok(conn) => {
+                println!("Connected to Orchestrator at {:?}", endpoint);
+                backoff = MIN_BACKOFF;
+                conn
+            }
+            Err(err) => {
+                println!(
+                    "Cannot connect to Orchestrator at {:?}: {}, retrying in {:?}",
+                    endpoint, err, backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+                continue;
+            }
+        };
+        loop {
+            let msg = match retry.take() {
+                Some(msg) => msg,
+                None => match rx.recv().await {
+                    Some(msg) => msg,
+                    // The exporter went away, we are shutting down.
+                    None => return,
+                },
+            };
+            match conn.send(&msg).await {
+                Ok(()) => {
+                    stats.sent.fetch_add(1, Ordering::Relaxed);
+                    stats.pending.fetch_sub(1, Ordering::Relaxed);
+                }
+                Err(err) if matches!(conn, Connection::Udp(_)) => {
+                    // Datagrams are fire and forget; typically this is Orchestrator
+                    // not listening (yet). There is no connection to repair.
+                    println!("Cannot send to Orchestrator: {}", err);
+                    stats.dropped.fetch_add(1, Ordering::Relaxed);
+                    stats.pending.fetch_sub(1, Ordering::Relaxed);
+                }
+                Err(err) => {
+                    // We don't know how much of the record made it, so we send all of
+                    // it again on the next connection.
+                    println!("Lost connection to Orchestrator: {}", err);
+                    stats.reconnects.fetch_add(1, Ordering::Relaxed);
+                    retry = Some(msg);
+                    break;
+                }
+            }
+        }
+    }
+}
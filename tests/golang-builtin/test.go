@@ -1,4 +1,4 @@
-// This one is mostly here to "prove" that Golang does not work.
+// Go links crypto/tls statically, so this is picked up by probing the executable.
 
 package main
 
@@ -0,0 +1,26 @@
+// Java does TLS in Java, so this is picked up by the Java agent that we load into
+// the JVM. That takes a moment after the JVM starts, hence the wait.
+
+import java.net.URI;
+import java.net.URL;
+import java.net.http.HttpClient;
+import java.net.http.HttpRequest;
+import java.net.http.HttpResponse;
+import javax.net.ssl.HttpsURLConnection;
+
+public class Test {
+    public static void main(String[] args) throws Exception {
+        Thread.sleep(5000);
+
+        // The HttpClient from Java 11 on, which uses SSLEngine.
+        HttpClient client = HttpClient.newHttpClient();
+        HttpResponse<String> response = client.send(
+                HttpRequest.newBuilder(URI.create("https://www.google.com")).build(),
+                HttpResponse.BodyHandlers.ofString());
+        System.out.println(response.body());
+
+        // The classic one, which uses SSLSocket.
+        HttpsURLConnection conn = (HttpsURLConnection) new URL("https://www.google.com").openConnection();
+        System.out.println(new String(conn.getInputStream().readAllBytes()));
+    }
+}